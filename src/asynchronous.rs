@@ -0,0 +1,389 @@
+//! The readers and writers in this module mirror `read::PcapReader` and `write::PcapWriter`, but
+//! work on asynchronous streams. There is one implementation for the traits of the `tokio` crate
+//! and one for those of the `futures` crate, each enabled by the cargo feature of the same name.
+//!
+//! Unlike the blocking reader, the asynchronous reader doesn't decompress captures, and rejects
+//! compressed streams with an error.
+//!
+//! The `async` methods are not cancellation safe: if the returned future is dropped before it
+//! completes, the position in the stream is lost.
+
+/// Generates `AsyncPcapReader` and `AsyncPcapWriter` for the `AsyncRead`, `AsyncWrite` and
+/// `AsyncSeek` traits (and their extension traits) that are in scope where it is invoked.
+macro_rules! async_pcap {
+    () => {
+        use std::convert::TryFrom;
+        use std::io;
+
+        use $crate::compress::Codec;
+        use $crate::def;
+        use $crate::read::{grow_buffer, invalid_record_size, read_file_header, read_record_header, truncated_packet, ReaderOptions};
+        use $crate::write::{encode_file_header, encode_record_header, WriteOptions};
+        use $crate::{CapturedPacket, FileOptions, PcapError, Rounding};
+
+        /// Reads and discards `len` bytes, or until EOF. Returns the number of bytes that were
+        /// skipped.
+        async fn skip<R: AsyncRead + Unpin>(reader: &mut R, len: u64) -> io::Result<u64> {
+            let mut buf = [0u8; 4096];
+            let mut skipped = 0;
+            while skipped < len {
+                let chunk = usize::try_from(len - skipped).map_or(buf.len(), |len| usize::min(len, buf.len()));
+                match reader.read(&mut buf[..chunk]).await {
+                    Ok(0) => break,
+                    Ok(read) => skipped += read as u64,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(skipped)
+        }
+
+        /// Reads into `buf` until it is full or EOF is reached. Returns the number of bytes that
+        /// were read.
+        async fn read_full<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+            let mut got = 0;
+            while got < buf.len() {
+                match reader.read(&mut buf[got..]).await {
+                    Ok(0) => break,
+                    Ok(read) => got += read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(got)
+        }
+
+        /// Reads the rest of a packet with `len` bytes into `buffer`, like the function of the
+        /// same name in the `read` module.
+        async fn read_into_buffer<R: AsyncRead + Unpin>(reader: &mut R, buffer: &mut Vec<u8>, mut got: usize, len: usize) -> io::Result<usize> {
+            while got < len {
+                let end = grow_buffer(buffer, got, len);
+                got += read_full(reader, &mut buffer[got..end]).await?;
+                if got < end {
+                    break;
+                }
+            }
+            Ok(got)
+        }
+
+        /// Reads and parses the file header of a packet capture in the traditional format.
+        async fn read_file_header_async<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(FileOptions, def::PcapFileHeader), PcapError> {
+            let mut buf = [0u8; 24];
+            let got = read_full(reader, &mut buf).await?;
+            if Codec::detect(&buf[..got]).is_some() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "compressed captures are not supported by the async reader").into());
+            }
+            if got < buf.len() {
+                return Err(PcapError::TruncatedFileHeader { expected: buf.len(), got });
+            }
+            read_file_header(&mut &buf[..])
+        }
+
+        /// The `AsyncPcapReader` struct allows reading packets from a packet capture in an
+        /// asynchronous stream.
+        pub struct AsyncPcapReader<R> {
+            reader: R,
+            state: Option<PcapState>,
+        }
+        struct PcapState {
+            file_header: def::PcapFileHeader,
+            packet_buffer: Vec<u8>,
+            options: ReaderOptions,
+            position: u64,
+            packets: u64,
+        }
+
+        impl<R: AsyncRead + Unpin> AsyncPcapReader<R> {
+            /// Create a new `AsyncPcapReader` that reads the packet capture data from the
+            /// specified stream.
+            ///
+            /// Fails with `io::ErrorKind::InvalidInput` if the stream is compressed with one of
+            /// the enabled formats, since only `PcapReader` decompresses captures.
+            pub async fn new(reader: R) -> Result<(FileOptions, Self), PcapError> {
+                AsyncPcapReader::with_options(reader, ReaderOptions::default()).await
+            }
+
+            /// Create a new `AsyncPcapReader` like `new`, but with the specified limits and
+            /// validation options.
+            pub async fn with_options(mut reader: R, options: ReaderOptions) -> Result<(FileOptions, Self), PcapError> {
+                let (mut opts, mut fh) = read_file_header_async(&mut reader).await?;
+                options.apply_to_file_header(&mut opts, &mut fh)?;
+
+                Ok((
+                    opts,
+                    AsyncPcapReader {
+                        reader,
+                        state: Some(PcapState {
+                            file_header: fh,
+                            packet_buffer: Vec::new(),
+                            options,
+                            position: def::PcapFileHeader::LEN,
+                            packets: 0,
+                        }),
+                    }
+                ))
+            }
+
+            /// Reads the next packet, like `PcapReader::next`.
+            ///
+            /// Returns `Ok(None)` on EOF, or a packet as long as one is available.
+            pub async fn next(&mut self) -> Result<Option<CapturedPacket<'_>>, PcapError> {
+                if self.state.is_none() {
+                    return Ok(None);
+                }
+
+                let mut buf = [0u8; 24];
+                let header_len = self.state.as_ref().unwrap().file_header.record_header_len() as usize;
+                let got = read_full(&mut self.reader, &mut buf[..header_len]).await?;
+                if got < header_len {
+                    let offset = self.state.take().unwrap().position;
+                    return if got == 0 {
+                        Ok(None)
+                    } else {
+                        Err(PcapError::TruncatedRecord { offset, expected: header_len, got })
+                    };
+                }
+                let state = self.state.as_mut().unwrap();
+                let (rh, extended) = read_record_header(&mut &buf[..header_len], &state.file_header)?;
+                let (offset, packet) = (state.position, state.packets);
+                state.position += header_len as u64 + u64::from(rh.incl_len);
+                state.packets += 1;
+
+                let size_in_pcap = usize::try_from(rh.incl_len).map_err(|_| invalid_record_size(&rh, packet, offset))?;
+                let size_to_read = match state.options.packet_size(&rh, &state.file_header, packet, offset) {
+                    Ok(size) => size,
+                    Err(e) => {
+                        skip(&mut self.reader, u64::from(rh.incl_len)).await?;
+                        return Err(e);
+                    },
+                };
+
+                let mut got = read_into_buffer(&mut self.reader, &mut state.packet_buffer, 0, size_to_read).await?;
+                if got == size_to_read && size_to_read < size_in_pcap {
+                    // drop the excessive data, like `PcapReader`
+                    got += skip(&mut self.reader, (size_in_pcap - size_to_read) as u64).await? as usize;
+                }
+                if got < size_in_pcap {
+                    let data = &state.packet_buffer[..usize::min(got, size_to_read)];
+                    let err = truncated_packet(&rh, extended, &state.file_header, packet, offset, data, size_in_pcap - got);
+                    self.state = None;
+                    return Err(err);
+                }
+                let state = self.state.as_ref().unwrap();
+                let buf = &state.packet_buffer[..size_to_read];
+
+                let orig_len = usize::try_from(rh.orig_len).map_err(|_| invalid_record_size(&rh, packet, offset))?;
+
+                if let Some(t) = rh.get_time(&state.file_header) {
+                    Ok(Some(CapturedPacket {
+                        time: t,
+                        data: buf,
+                        orig_len,
+                        extended,
+                    }))
+                } else {
+                    Err(PcapError::InvalidDate)
+                }
+            }
+
+            /// Destroys this `AsyncPcapReader` and returns access to the underlying stream.
+            pub fn take_reader(self) -> R {
+                self.reader
+            }
+        }
+
+        /// The `AsyncPcapWriter` struct allows writing packets to a packet capture in an
+        /// asynchronous stream.
+        pub struct AsyncPcapWriter<W> {
+            writer: W,
+            opts: WriteOptions,
+            rounding: Rounding,
+            lossy_timestamps: u64,
+        }
+
+        impl<W: AsyncWrite + Unpin> AsyncPcapWriter<W> {
+            /// Create a new `AsyncPcapWriter` that writes the packet capture data to the specified
+            /// stream.
+            pub async fn new(mut writer: W, opts: WriteOptions) -> Result<Self, PcapError> {
+                writer.write_all(&encode_file_header(opts)?).await?;
+
+                AsyncPcapWriter::append_unchecked(writer, opts)
+            }
+
+            /// Create a new `AsyncPcapWriter` that appends the packets to an existing stream, like
+            /// `PcapWriter::append_unchecked`.
+            pub fn append_unchecked(writer: W, opts: WriteOptions) -> Result<Self, PcapError> {
+                Ok(AsyncPcapWriter { writer, opts, rounding: Rounding::default(), lossy_timestamps: 0 })
+            }
+
+            /// Create a new `AsyncPcapWriter` that appends the packets to an existing stream,
+            /// which must be readable and seekable so that the correct format options for the
+            /// file can be determined.
+            ///
+            /// Fails with `io::ErrorKind::InvalidInput` if the stream is compressed, because
+            /// there is no asynchronous equivalent of `PcapWriter::append_compressed`.
+            pub async fn append(mut stream: W) -> Result<Self, PcapError>
+                where W: AsyncRead + AsyncSeek
+            {
+                stream.seek(io::SeekFrom::Start(0)).await?;
+                let mut buf = [0u8; 24];
+                let got = read_full(&mut stream, &mut buf).await?;
+                if Codec::detect(&buf[..got]).is_some() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "compressed captures can't be appended to asynchronously").into());
+                }
+                if got < buf.len() {
+                    return Err(PcapError::TruncatedFileHeader { expected: buf.len(), got });
+                }
+                let (opts, _) = read_file_header(&mut &buf[..])?;
+                stream.seek(io::SeekFrom::End(0)).await?;
+
+                AsyncPcapWriter::append_unchecked(stream, opts)
+            }
+
+            /// Sets how timestamps are rounded, like `PcapWriter::set_rounding`.
+            pub fn set_rounding(&mut self, rounding: Rounding) {
+                self.rounding = rounding;
+            }
+
+            /// The number of packets written so far whose timestamp had to be rounded.
+            pub fn lossy_timestamps(&self) -> u64 {
+                self.lossy_timestamps
+            }
+
+            /// Write a packet to the capture file.
+            pub async fn write(&mut self, packet: &CapturedPacket<'_>) -> Result<(), PcapError> {
+                let (record_header, len) = encode_record_header(&self.opts, self.rounding, packet)?;
+                self.lossy_timestamps += u64::from(record_header.lossy_timestamp);
+                self.writer.write_all(&record_header).await?;
+                self.writer.write_all(&packet.data[..len]).await.map_err(PcapError::from)
+            }
+
+            /// Flushes the underlying stream.
+            pub async fn flush(&mut self) -> Result<(), io::Error> {
+                self.writer.flush().await
+            }
+
+            /// Destroys this `AsyncPcapWriter` and returns access to the underlying stream.
+            pub fn take_writer(self) -> W {
+                self.writer
+            }
+
+            /// The options used by this `AsyncPcapWriter`.
+            pub fn get_options(&self) -> WriteOptions {
+                self.opts
+            }
+        }
+
+        #[cfg(test)]
+        mod test {
+            use super::*;
+            use $crate::read::PcapSliceReader;
+
+            #[test]
+            /// Writes, appends to and reads back a capture, comparing with the blocking reader.
+            fn read_write() {
+                let pcap = [0xd4, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x65, 0x00, 0x00, 0x00,
+                            0x1a, 0x6e, 0x50, 0x56, 0xe2, 0x2f, 0x06, 0x00, 0x04, 0x00, 0x00, 0x00,
+                            0x3c, 0x00, 0x00, 0x00, 0x44, 0x41, 0x54, 0x41];
+                let (opts, reader) = PcapSliceReader::new(&pcap).unwrap();
+                let packet = reader.map(Result::unwrap).next().unwrap();
+
+                futures_executor::block_on(async {
+                    let mut writer = AsyncPcapWriter::new(TestCursor::new(Vec::new()), opts).await.unwrap();
+                    writer.write(&packet).await.unwrap();
+                    let mut writer = AsyncPcapWriter::append(writer.take_writer()).await.unwrap();
+                    assert_eq!(writer.get_options(), opts);
+                    writer.write(&packet).await.unwrap();
+                    let file = writer.take_writer().into_inner();
+
+                    let mut expected = pcap.to_vec();
+                    expected.extend_from_slice(&pcap[24..]);
+                    assert_eq!(file, expected);
+
+                    let (ropts, mut reader) = AsyncPcapReader::new(&file[..]).await.unwrap();
+                    assert_eq!(ropts, opts);
+                    for _ in 0..2 {
+                        assert_eq!(reader.next().await.unwrap().unwrap(), packet);
+                    }
+                    assert!(reader.next().await.unwrap().is_none());
+                });
+            }
+
+            #[test]
+            #[cfg(feature = "gzip")]
+            /// Makes sure compressed captures are not appended to as if they were uncompressed.
+            fn append_to_compressed() {
+                let compress = $crate::compress::Compress::new(Vec::new(), Codec::Gzip, 6).unwrap();
+                let file = $crate::write::PcapWriter::new(compress, $crate::test::write_options()).unwrap().take_writer().finish().unwrap();
+
+                futures_executor::block_on(async {
+                    let result = AsyncPcapWriter::append(TestCursor::new(file)).await;
+                    assert!(matches!(result, Err(PcapError::Io(ref e)) if e.kind() == io::ErrorKind::InvalidInput));
+                });
+            }
+
+            #[test]
+            #[cfg(feature = "gzip")]
+            /// Makes sure compressed captures are rejected with a clear error instead of a bad
+            /// magic number.
+            fn read_compressed() {
+                let compress = $crate::compress::Compress::new(Vec::new(), Codec::Gzip, 6).unwrap();
+                let file = $crate::write::PcapWriter::new(compress, $crate::test::write_options()).unwrap().take_writer().finish().unwrap();
+                assert!($crate::read::PcapReader::new(&file[..]).is_ok());
+
+                futures_executor::block_on(async {
+                    let result = AsyncPcapReader::new(&file[..]).await;
+                    assert!(matches!(result, Err(PcapError::Io(ref e)) if e.kind() == io::ErrorKind::InvalidInput));
+                });
+            }
+
+            #[test]
+            /// Makes sure a record that is cut off after the snap length is reported as truncated,
+            /// like by the blocking reader.
+            fn truncated_after_snaplen() {
+                // snap length 2, but the record claims 6 bytes, and only 4 are stored
+                let pcap = [0xd4, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00, 0x00,
+                            0x1a, 0x6e, 0x50, 0x56, 0xe2, 0x2f, 0x06, 0x00, 0x06, 0x00, 0x00, 0x00,
+                            0x06, 0x00, 0x00, 0x00, 0x44, 0x41, 0x54, 0x41];
+                let (_, mut reader) = $crate::read::PcapReader::new(&pcap[..]).unwrap();
+                let expected = match reader.next() {
+                    Err(PcapError::TruncatedPacket(truncated)) => truncated,
+                    result => panic!("unexpected result {:?}", result),
+                };
+                assert_eq!((&expected.packet.data[..], expected.missing), (&b"DA"[..], 2));
+                assert!(reader.next().unwrap().is_none());
+
+                futures_executor::block_on(async {
+                    let (_, mut reader) = AsyncPcapReader::new(&pcap[..]).await.unwrap();
+                    match reader.next().await {
+                        Err(PcapError::TruncatedPacket(truncated)) => assert_eq!(truncated, expected),
+                        result => panic!("unexpected result {:?}", result),
+                    }
+                    assert!(reader.next().await.unwrap().is_none());
+                });
+            }
+        }
+    };
+}
+
+/// Asynchronous reading and writing for the I/O traits of the `tokio` crate.
+#[cfg(feature = "tokio")]
+pub mod tokio {
+    use ::tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+    #[cfg(test)]
+    type TestCursor<T> = std::io::Cursor<T>;
+
+    async_pcap!();
+}
+
+/// Asynchronous reading and writing for the I/O traits of the `futures` crate.
+#[cfg(feature = "futures")]
+pub mod futures {
+    use futures_util::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+    #[cfg(test)]
+    type TestCursor<T> = futures_util::io::Cursor<T>;
+
+    async_pcap!();
+}
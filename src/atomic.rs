@@ -0,0 +1,336 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::write::{PcapWriter, WriteOptions};
+use super::CapturedPacket;
+use super::PcapError;
+use super::Rounding;
+
+
+/// How often `AtomicPcapWriter` tries another name for the temporary file if the name is taken.
+const TEMP_NAME_ATTEMPTS: u32 = 100;
+/// Makes the names of temporary files unique within this process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The `SyncPolicy` struct decides how often an `AtomicPcapWriter` makes the packets written so
+/// far durable, by flushing them and calling `fsync`. By default, this only happens in `finish`.
+#[derive(Copy,Clone,PartialEq,Eq,Debug,Default)]
+pub struct SyncPolicy {
+    packets: Option<u64>,
+    interval: Option<Duration>,
+}
+
+impl SyncPolicy {
+    /// Creates a policy that only syncs when the file is finished.
+    pub fn new() -> Self {
+        SyncPolicy::default()
+    }
+
+    /// Syncs after every `packets` packets.
+    pub fn packets(mut self, packets: u64) -> Self {
+        self.packets = Some(packets);
+        self
+    }
+
+    /// Syncs when a packet is written and the last sync was at least `interval` ago.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+}
+
+/// The `AtomicPcapWriter` struct writes a capture file so that it only appears at its path once
+/// it is complete. A crash never leaves a partially written file there, and never replaces an
+/// existing file with one.
+///
+/// The packets are written to a temporary file in the same directory, which `finish` syncs to
+/// disk and then renames to the final path. If the writer is dropped without calling `finish`,
+/// the temporary file is deleted. After a crash, the temporary file contains at least all packets
+/// up to the last sync of the `SyncPolicy`.
+///
+/// ```no_run
+/// use rpcap::atomic::AtomicPcapWriter;
+/// # use rpcap::read::PcapReader;
+/// # let (opts, mut reader) = PcapReader::new(std::io::empty()).unwrap();
+///
+/// let mut writer = AtomicPcapWriter::create("out.pcap", opts).unwrap();
+/// while let Some(packet) = reader.next().unwrap() {
+///     writer.write(&packet).unwrap();
+/// }
+/// writer.finish().unwrap();
+/// ```
+pub struct AtomicPcapWriter {
+    writer: Option<PcapWriter<BufWriter<File>>>,
+    path: PathBuf,
+    temp_path: PathBuf,
+    policy: SyncPolicy,
+    unsynced_packets: u64,
+    last_sync: Instant,
+}
+
+impl AtomicPcapWriter {
+    /// Create a new `AtomicPcapWriter` that writes a capture file with the options `opts` to
+    /// `path`, and only syncs it to disk in `finish`.
+    pub fn create<P: AsRef<Path>>(path: P, opts: WriteOptions) -> Result<Self, PcapError> {
+        AtomicPcapWriter::with_policy(path, opts, SyncPolicy::default())
+    }
+
+    /// Create a new `AtomicPcapWriter` like `create`, that additionally syncs the packets to disk
+    /// according to `policy`.
+    pub fn with_policy<P: AsRef<Path>>(path: P, opts: WriteOptions, policy: SyncPolicy) -> Result<Self, PcapError> {
+        let path = path.as_ref().to_path_buf();
+        let (file, temp_path) = create_temp_file(&path)?;
+        let writer = match PcapWriter::new(BufWriter::new(file), opts) {
+            Ok(writer) => writer,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e);
+            },
+        };
+        Ok(AtomicPcapWriter {
+            writer: Some(writer),
+            path,
+            temp_path,
+            policy,
+            unsynced_packets: 0,
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Sets how timestamps are rounded, like `PcapWriter::set_rounding`.
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.writer_mut().set_rounding(rounding);
+    }
+
+    /// The number of packets written so far whose timestamp had to be rounded.
+    pub fn lossy_timestamps(&self) -> u64 {
+        self.writer().lossy_timestamps()
+    }
+
+    /// Write a packet to the temporary file, and sync it if the `SyncPolicy` says so.
+    pub fn write(&mut self, packet: &CapturedPacket) -> Result<(), PcapError> {
+        self.writer_mut().write(packet)?;
+        self.unsynced_packets += 1;
+
+        let by_packets = self.policy.packets.is_some_and(|packets| self.unsynced_packets >= packets);
+        let by_time = self.policy.interval.is_some_and(|interval| self.last_sync.elapsed() >= interval);
+        if by_packets || by_time {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the packets written so far to the temporary file, without syncing them to disk.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        self.writer_mut().flush()
+    }
+
+    /// Flushes the packets written so far to the temporary file and syncs it to disk, so that only
+    /// complete records are lost in a crash.
+    pub fn sync(&mut self) -> Result<(), io::Error> {
+        let writer = self.writer.as_mut().unwrap();
+        writer.flush()?;
+        writer.get_ref().get_ref().sync_data()?;
+        self.unsynced_packets = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Completes the file: syncs it to disk, and then atomically replaces the file at the final
+    /// path with it.
+    ///
+    /// If flushing or syncing the packets fails, the temporary file is deleted. If the rename
+    /// fails, the complete temporary file is kept, and the error names its path. If only syncing
+    /// the directory fails after the rename, the file is at the final path, but the rename may not
+    /// survive a crash.
+    pub fn finish(mut self) -> Result<(), PcapError> {
+        let writer = self.writer.take().unwrap();
+        let synced = writer.take_writer().into_inner().map_err(io::IntoInnerError::into_error)
+            .and_then(|file| file.sync_all());
+        if let Err(e) = synced {
+            let _ = fs::remove_file(&self.temp_path);
+            return Err(e.into());
+        }
+
+        if let Err(e) = fs::rename(&self.temp_path, &self.path) {
+            let msg = format!("failed to rename {} to {}, the file was kept: {}",
+                              self.temp_path.display(), self.path.display(), e);
+            return Err(io::Error::new(e.kind(), msg).into());
+        }
+        sync_dir(&self.path).map_err(|e| {
+            let msg = format!("{} was written, but syncing its directory failed: {}", self.path.display(), e);
+            io::Error::new(e.kind(), msg).into()
+        })
+    }
+
+    /// The path the file is written to once it is finished.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of the temporary file that the packets are written to until the file is finished.
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// The options used by this `AtomicPcapWriter`.
+    pub fn get_options(&self) -> WriteOptions {
+        self.writer().get_options()
+    }
+
+    fn writer(&self) -> &PcapWriter<BufWriter<File>> {
+        self.writer.as_ref().unwrap()
+    }
+
+    fn writer_mut(&mut self) -> &mut PcapWriter<BufWriter<File>> {
+        self.writer.as_mut().unwrap()
+    }
+}
+
+impl Drop for AtomicPcapWriter {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Creates a new temporary file next to `path`, named `.{name}.{pid}.{counter}.tmp`. If a file
+/// with that name exists already, e.g. one that was left behind by a crashed process with the
+/// same pid, the next counter value is tried.
+fn create_temp_file(path: &Path) -> io::Result<(File, PathBuf)> {
+    create_temp_file_from(path, &TEMP_COUNTER)
+}
+
+/// Creates a new temporary file like `create_temp_file`, but takes the counter values from
+/// `counter`.
+fn create_temp_file_from(path: &Path, counter: &AtomicU64) -> io::Result<(File, PathBuf)> {
+    let file_name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    for _ in 0..TEMP_NAME_ATTEMPTS {
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{}.{}.tmp", process::id(), counter.fetch_add(1, Ordering::Relaxed)));
+        let temp_path = path.with_file_name(temp_name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((file, temp_path)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no unused name for the temporary file found"))
+}
+
+/// Syncs the directory that contains `path`, so that a rename in it is durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::read::PcapReader;
+    use super::super::test::write_options;
+    use super::super::Timestamp;
+
+    #[test]
+    /// Makes sure the file only replaces the existing one once it is finished, and the packets are
+    /// synced according to the policy.
+    fn atomic_replace() {
+        let path = std::env::temp_dir().join(format!("rpcap-atomic-{}.pcap", process::id()));
+        fs::write(&path, b"old").unwrap();
+        let time = Timestamp::new(1_000_000_000, 0).unwrap().to_time().unwrap();
+
+        let mut writer = AtomicPcapWriter::with_policy(&path, write_options(), SyncPolicy::new().packets(2)).unwrap();
+        let temp_path = writer.temp_path().to_path_buf();
+        let temp_len = || fs::metadata(&temp_path).unwrap().len();
+        for i in 0..3u8 {
+            writer.write(&CapturedPacket { time, data: &[i; 4], orig_len: 4, extended: None }).unwrap();
+        }
+        assert_eq!(temp_len(), 24 + 2 * 20);
+        writer.sync().unwrap();
+        assert_eq!(temp_len(), 24 + 3 * 20);
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        writer.finish().unwrap();
+
+        assert!(!temp_path.exists());
+        let (_, reader) = PcapReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.into_owned_iter().map(|packet| packet.unwrap().data[0]).collect::<Vec<_>>(), [0, 1, 2]);
+
+        let mut writer = AtomicPcapWriter::create(&path, write_options()).unwrap();
+        writer.write(&CapturedPacket { time, data: &[9; 4], orig_len: 4, extended: None }).unwrap();
+        drop(writer);
+        assert!(!temp_path.exists());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 24 + 3 * 20);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    /// Makes sure a file that can't be renamed is kept, and its path is reported.
+    fn failed_rename() {
+        // a non-empty directory can't be replaced by a file
+        let path = std::env::temp_dir().join(format!("rpcap-atomic-dir-{}", process::id()));
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("file"), b"").unwrap();
+
+        let mut writer = AtomicPcapWriter::create(&path, write_options()).unwrap();
+        let temp_path = writer.temp_path().to_path_buf();
+        let time = Timestamp::new(1_000_000_000, 0).unwrap().to_time().unwrap();
+        writer.write(&CapturedPacket { time, data: &[1; 4], orig_len: 4, extended: None }).unwrap();
+        let err = writer.finish().unwrap_err();
+        assert!(err.to_string().contains(&*temp_path.to_string_lossy()));
+        assert_eq!(fs::metadata(&temp_path).unwrap().len(), 24 + 20);
+
+        fs::remove_file(&temp_path).unwrap();
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    /// Makes sure concurrent writers for the same path, and stale temporary files, don't collide.
+    fn unique_temp_files() {
+        let path = std::env::temp_dir().join(format!("rpcap-atomic-unique-{}.pcap", process::id()));
+        let first = AtomicPcapWriter::create(&path, write_options()).unwrap();
+        let second = AtomicPcapWriter::create(&path, write_options()).unwrap();
+        assert_ne!(first.temp_path(), second.temp_path());
+        second.finish().unwrap();
+        drop(first);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 24);
+
+        // the counter is shared with the other tests, so use one that starts at a known value
+        let stale: Vec<_> = (0..2).map(|n| {
+            let mut stale = std::ffi::OsString::from(".");
+            stale.push(path.file_name().unwrap());
+            stale.push(format!(".{}.{}.tmp", process::id(), n));
+            let stale = path.with_file_name(stale);
+            fs::write(&stale, b"stale").unwrap();
+            stale
+        }).collect();
+        let counter = AtomicU64::new(0);
+        let (_, temp_path) = create_temp_file_from(&path, &counter).unwrap();
+        assert!(!stale.contains(&temp_path));
+        assert_eq!(counter.load(Ordering::Relaxed), 3);
+        for stale in &stale {
+            assert_eq!(fs::read(stale).unwrap(), b"stale");
+            fs::remove_file(stale).unwrap();
+        }
+
+        fs::remove_file(&temp_path).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+use std::io;
+use std::io::Read;
+
+#[cfg(feature = "gzip")]
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+#[cfg(feature = "xz")]
+use xz2::{read::XzDecoder, stream::{Check, Stream}, write::XzEncoder};
+#[cfg(feature = "lz4")]
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+
+
+/// The compression formats supported by this crate. Each one has to be enabled with the cargo
+/// feature of the same name.
+///
+/// Since cargo features are additive, more variants may exist in a build than the ones your crate
+/// enabled, so matches on `Codec` need a wildcard arm.
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+#[non_exhaustive]
+pub enum Codec {
+    /// gzip compression (`.pcap.gz`)
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// Zstandard compression (`.pcap.zst`)
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// xz compression (`.pcap.xz`)
+    #[cfg(feature = "xz")]
+    Xz,
+    /// LZ4 frame compression (`.pcap.lz4`)
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+/// The magic numbers at the start of compressed files.
+const CODEC_MAGICS: &[(Codec, &[u8])] = &[
+    #[cfg(feature = "gzip")]
+    (Codec::Gzip, &[0x1f, 0x8b]),
+    #[cfg(feature = "zstd")]
+    (Codec::Zstd, &[0x28, 0xb5, 0x2f, 0xfd]),
+    #[cfg(feature = "xz")]
+    (Codec::Xz, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+    #[cfg(feature = "lz4")]
+    (Codec::Lz4, &[0x04, 0x22, 0x4d, 0x18]),
+];
+/// The length of the longest magic number in `CODEC_MAGICS`.
+const MAX_MAGIC_LEN: usize = 6;
+
+impl Codec {
+    /// Detects the compression format from the first bytes of a file. Returns `None` if the data
+    /// is not compressed with any of the enabled formats.
+    pub fn detect(data: &[u8]) -> Option<Codec> {
+        CODEC_MAGICS.iter()
+            .find(|(_, magic)| data.starts_with(magic))
+            .map(|&(codec, _)| codec)
+    }
+}
+
+
+/// The reader passed to the decompressor, which replays the bytes that were needed to detect the
+/// compression format.
+type Sniffed<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// A `Read` adapter that transparently decompresses its input, if it is compressed with one of the
+/// enabled formats. Uncompressed input is passed through unchanged.
+///
+/// Concatenated compressed streams are read one after another, so files that were appended to
+/// with `PcapWriter::append_compressed` can be read completely.
+pub struct Decompress<R: io::Read> {
+    inner: DecompressInner<R>,
+}
+enum DecompressInner<R: io::Read> {
+    Plain(Sniffed<R>),
+    #[cfg(feature = "gzip")]
+    Gzip(MultiGzDecoder<Sniffed<R>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::read::Decoder<'static, io::BufReader<Sniffed<R>>>),
+    #[cfg(feature = "xz")]
+    Xz(XzDecoder<Sniffed<R>>),
+    #[cfg(feature = "lz4")]
+    Lz4(FrameDecoder<Counting<Sniffed<R>>>),
+}
+
+/// Counts the bytes read from the inner reader. The LZ4 decoder reports EOF at the end of every
+/// frame, this is used to tell whether there might be another frame following it.
+#[cfg(feature = "lz4")]
+struct Counting<R> {
+    inner: R,
+    count: u64,
+}
+#[cfg(feature = "lz4")]
+impl<R: io::Read> io::Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: io::Read> Decompress<R> {
+    /// Create a new `Decompress` that reads from the specified `Read`. The compression format is
+    /// detected from the first few bytes.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = vec![0; MAX_MAGIC_LEN];
+        let mut len = 0;
+        while len < magic.len() {
+            match reader.read(&mut magic[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        magic.truncate(len);
+
+        let codec = Codec::detect(&magic);
+        let reader = io::Cursor::new(magic).chain(reader);
+        let inner = match codec {
+            None => DecompressInner::Plain(reader),
+            #[cfg(feature = "gzip")]
+            Some(Codec::Gzip) => DecompressInner::Gzip(MultiGzDecoder::new(reader)),
+            #[cfg(feature = "zstd")]
+            Some(Codec::Zstd) => DecompressInner::Zstd(zstd::stream::read::Decoder::new(reader)?),
+            #[cfg(feature = "xz")]
+            Some(Codec::Xz) => DecompressInner::Xz(XzDecoder::new_multi_decoder(reader)),
+            #[cfg(feature = "lz4")]
+            Some(Codec::Lz4) => DecompressInner::Lz4(FrameDecoder::new(Counting { inner: reader, count: 0 })),
+        };
+        Ok(Decompress { inner })
+    }
+
+    /// The detected compression format, or `None` if the input is not compressed.
+    pub fn codec(&self) -> Option<Codec> {
+        match self.inner {
+            DecompressInner::Plain(_) => None,
+            #[cfg(feature = "gzip")]
+            DecompressInner::Gzip(_) => Some(Codec::Gzip),
+            #[cfg(feature = "zstd")]
+            DecompressInner::Zstd(_) => Some(Codec::Zstd),
+            #[cfg(feature = "xz")]
+            DecompressInner::Xz(_) => Some(Codec::Xz),
+            #[cfg(feature = "lz4")]
+            DecompressInner::Lz4(_) => Some(Codec::Lz4),
+        }
+    }
+
+    /// Gives access to the underlying `Read` if the input is not compressed. Positions in
+    /// compressed input don't correspond to positions in the underlying `Read`, so it can't be
+    /// accessed in that case.
+    #[allow(unreachable_patterns)]
+    pub(crate) fn uncompressed_mut(&mut self) -> Option<&mut R> {
+        match self.inner {
+            DecompressInner::Plain(ref mut r) => Some(r.get_mut().1),
+            _ => None,
+        }
+    }
+
+    /// Destroys this `Decompress` and returns access to the underlying `Read`. Any data that was
+    /// buffered by the decompressor is lost.
+    #[allow(clippy::infallible_destructuring_match)]
+    pub fn into_inner(self) -> R {
+        let sniffed = match self.inner {
+            DecompressInner::Plain(r) => r,
+            #[cfg(feature = "gzip")]
+            DecompressInner::Gzip(r) => r.into_inner(),
+            #[cfg(feature = "zstd")]
+            DecompressInner::Zstd(r) => r.finish().into_inner(),
+            #[cfg(feature = "xz")]
+            DecompressInner::Xz(r) => r.into_inner(),
+            #[cfg(feature = "lz4")]
+            DecompressInner::Lz4(r) => r.into_inner().inner,
+        };
+        sniffed.into_inner().1
+    }
+}
+
+impl<R: io::Read> io::Read for Decompress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner {
+            DecompressInner::Plain(ref mut r) => r.read(buf),
+            #[cfg(feature = "gzip")]
+            DecompressInner::Gzip(ref mut r) => r.read(buf),
+            #[cfg(feature = "zstd")]
+            DecompressInner::Zstd(ref mut r) => r.read(buf),
+            #[cfg(feature = "xz")]
+            DecompressInner::Xz(ref mut r) => r.read(buf),
+            #[cfg(feature = "lz4")]
+            DecompressInner::Lz4(ref mut r) => loop {
+                let before = r.get_ref().count;
+                let len = r.read(buf)?;
+                if len != 0 || buf.is_empty() || r.get_ref().count == before {
+                    break Ok(len);
+                }
+            },
+        }
+    }
+}
+
+
+/// A `Write` adapter that compresses the written data with the selected format.
+///
+/// `finish` has to be called after all data was written, or the output might be incomplete.
+pub struct Compress<W: io::Write> {
+    inner: CompressInner<W>,
+}
+enum CompressInner<W: io::Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    #[cfg(feature = "xz")]
+    Xz(XzEncoder<W>),
+    #[cfg(feature = "lz4")]
+    Lz4(FrameEncoder<W>),
+}
+
+impl<W: io::Write> Compress<W> {
+    /// Create a new `Compress` that writes data compressed with `codec` to the specified `Write`.
+    ///
+    /// The meaning of `level` depends on the format: for gzip and xz it ranges from `0` to `9`,
+    /// for Zstandard from `1` to `22` (`0` selects the default level). LZ4 ignores the level.
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd", feature = "xz", feature = "lz4")), allow(unused_variables, unreachable_code))]
+    pub fn new(writer: W, codec: Codec, level: u32) -> io::Result<Self> {
+        let inner = match codec {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => {
+                if level > 9 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "gzip compression levels range from 0 to 9"));
+                }
+                CompressInner::Gzip(GzEncoder::new(writer, flate2::Compression::new(level)))
+            },
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                let level = i32::try_from(level).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+                CompressInner::Zstd(zstd::stream::write::Encoder::new(writer, level)?)
+            },
+            #[cfg(feature = "xz")]
+            Codec::Xz => {
+                let stream = Stream::new_easy_encoder(level, Check::Crc64)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                CompressInner::Xz(XzEncoder::new_stream(writer, stream))
+            },
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => {
+                let _ = level;
+                CompressInner::Lz4(FrameEncoder::new(writer))
+            },
+        };
+        Ok(Compress { inner })
+    }
+
+    /// Create a new `Compress` that passes all data through to the specified `Write` unchanged.
+    pub fn uncompressed(writer: W) -> Self {
+        Compress { inner: CompressInner::Plain(writer) }
+    }
+
+    /// The selected compression format, or `None` if the data is not compressed.
+    pub fn codec(&self) -> Option<Codec> {
+        match self.inner {
+            CompressInner::Plain(_) => None,
+            #[cfg(feature = "gzip")]
+            CompressInner::Gzip(_) => Some(Codec::Gzip),
+            #[cfg(feature = "zstd")]
+            CompressInner::Zstd(_) => Some(Codec::Zstd),
+            #[cfg(feature = "xz")]
+            CompressInner::Xz(_) => Some(Codec::Xz),
+            #[cfg(feature = "lz4")]
+            CompressInner::Lz4(_) => Some(Codec::Lz4),
+        }
+    }
+
+    /// Writes the end of the compressed stream, and returns access to the underlying `Write`.
+    pub fn finish(self) -> io::Result<W> {
+        match self.inner {
+            CompressInner::Plain(w) => Ok(w),
+            #[cfg(feature = "gzip")]
+            CompressInner::Gzip(w) => w.finish(),
+            #[cfg(feature = "zstd")]
+            CompressInner::Zstd(w) => w.finish(),
+            #[cfg(feature = "xz")]
+            CompressInner::Xz(w) => w.finish(),
+            #[cfg(feature = "lz4")]
+            CompressInner::Lz4(w) => w.finish().map_err(io::Error::from),
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for Compress<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner {
+            CompressInner::Plain(ref mut w) => w.write(buf),
+            #[cfg(feature = "gzip")]
+            CompressInner::Gzip(ref mut w) => w.write(buf),
+            #[cfg(feature = "zstd")]
+            CompressInner::Zstd(ref mut w) => w.write(buf),
+            #[cfg(feature = "xz")]
+            CompressInner::Xz(ref mut w) => w.write(buf),
+            #[cfg(feature = "lz4")]
+            CompressInner::Lz4(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            CompressInner::Plain(ref mut w) => w.flush(),
+            #[cfg(feature = "gzip")]
+            CompressInner::Gzip(ref mut w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            CompressInner::Zstd(ref mut w) => w.flush(),
+            #[cfg(feature = "xz")]
+            CompressInner::Xz(ref mut w) => w.flush(),
+            #[cfg(feature = "lz4")]
+            CompressInner::Lz4(ref mut w) => w.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    use super::super::read::{open_any, PcapReader};
+    use super::super::write::{PcapWriter, WriteOptions};
+    use super::super::test::write_options;
+    use super::super::{CapturedPacket, Linktype};
+
+    const CODECS: &[Codec] = &[
+        #[cfg(feature = "gzip")]
+        Codec::Gzip,
+        #[cfg(feature = "zstd")]
+        Codec::Zstd,
+        #[cfg(feature = "xz")]
+        Codec::Xz,
+        #[cfg(feature = "lz4")]
+        Codec::Lz4,
+    ];
+
+    #[test]
+    /// Writes, appends to and reads back compressed captures.
+    fn compressed_roundtrip() {
+        let opts = WriteOptions {
+            snaplen: 1000,
+            linktype: Linktype::ETHERNET,
+            ..write_options()
+        };
+        #[cfg(not(feature = "time"))]
+        let time = std::time::UNIX_EPOCH;
+        #[cfg(feature = "time")]
+        let time = ::time::Timespec::new(0, 0);
+        let packet = CapturedPacket {
+            time,
+            data: &[0x42; 100],
+            orig_len: 100,
+            extended: None,
+        };
+
+        for codec in CODECS.iter().map(|&c| Some(c)).chain(Some(None)) {
+            let writer = match codec {
+                Some(codec) => Compress::new(Cursor::new(Vec::new()), codec, 3).unwrap(),
+                None => Compress::uncompressed(Cursor::new(Vec::new())),
+            };
+            let mut pcapw = PcapWriter::new(writer, opts).unwrap();
+            pcapw.write(&packet).unwrap();
+            let file = pcapw.take_writer().finish().unwrap();
+
+            let mut pcapw = PcapWriter::append_compressed(file, 3).unwrap();
+            assert_eq!(pcapw.get_options(), opts);
+            pcapw.write(&packet).unwrap();
+            let mut file = pcapw.take_writer().finish().unwrap();
+
+            file.seek(SeekFrom::Start(0)).unwrap();
+            let mut reader = open_any(&mut file).unwrap();
+            assert_eq!(reader.codec(), codec);
+            for _ in 0..2 {
+                assert_eq!(reader.next().unwrap().unwrap().packet, packet);
+            }
+            assert!(reader.next().unwrap().is_none());
+
+            let data = file.into_inner();
+            let (ropts, mut reader) = PcapReader::new(&data[..]).unwrap();
+            assert_eq!(ropts, opts);
+            assert_eq!(reader.codec(), codec);
+            for _ in 0..2 {
+                assert_eq!(reader.next().unwrap().unwrap(), packet);
+            }
+            assert!(reader.next().unwrap().is_none());
+            assert_eq!(codec.is_none(), PcapWriter::append(Cursor::new(data)).is_ok());
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "gzip", feature = "xz"))]
+    /// Makes sure invalid compression levels are rejected instead of panicking.
+    fn invalid_levels() {
+        let invalid = |codec, level| {
+            let result = Compress::new(Vec::new(), codec, level);
+            matches!(result, Err(ref e) if e.kind() == io::ErrorKind::InvalidInput)
+        };
+        #[cfg(feature = "gzip")]
+        {
+            assert!(Compress::new(Vec::new(), Codec::Gzip, 9).is_ok());
+            assert!(invalid(Codec::Gzip, 10));
+        }
+        #[cfg(feature = "xz")]
+        {
+            assert!(Compress::new(Vec::new(), Codec::Xz, 9).is_ok());
+            assert!(invalid(Codec::Xz, 10));
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use bytepack::Packed;
 
-#[cfg(not(feature = "time"))]
-use std::time::{Duration, UNIX_EPOCH};
-
-use super::{FileOptions, Time};
+use super::{ExtendedRecord, FileOptions, InterfaceOptions, PcapError, Resolution, Rounding, Time, Timestamp};
 
 use std::convert::{TryFrom,TryInto};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::str::FromStr;
 
 
 
@@ -36,19 +37,15 @@ const PCAP_VERSION_MINOR: u16 = 4;
 impl PcapFileHeaderInFile {
     /// Creates a new file header based on snap length and link type.
     pub fn new(opts: FileOptions) -> Option<PcapFileHeaderInFile> {
-        let magic = if opts.high_res_timestamps {
-            PcapMagic::NanoSecondResolution
-        } else {
-            PcapMagic::Normal
-        };
+        let magic = PcapMagic::new(opts.variant, opts.high_res_timestamps)?;
         Some(PcapFileHeaderInFile {
             magic_num: magic.into(),
             version_major: PCAP_VERSION_MAJOR,
             version_minor: PCAP_VERSION_MINOR,
-            thiszone: 0,
-            sigfigs: 0,
+            thiszone: opts.utc_offset,
+            sigfigs: opts.sigfigs,
             snaplen: u32::try_from(opts.snaplen).ok()?,
-            network: opts.linktype,
+            network: join_network(opts.linktype, opts.fcs_length, opts.linktype_reserved)?,
         })
     }
 }
@@ -72,23 +69,28 @@ pub struct PcapFileHeader {
     pub need_byte_swap: bool,
     /// The datalink type.
     pub network: u32,
-    /// The UTC offset of the timestamps.
+    /// The UTC offset that is added to the timestamps.
     pub utc_offset: i32,
+    /// The accuracy of the timestamps, as stored in the file.
+    pub sigfigs: u32,
     /// The maximum size of captured packets.
     pub snaplen: usize,
+    /// The variant of the file format.
+    pub variant: PcapVariant,
 }
 impl TryFrom<PcapFileHeaderInFile> for PcapFileHeader {
-    type Error = ();
+    type Error = PcapError;
 
     /// Parse header data from file.
     fn try_from(mut header: PcapFileHeaderInFile) -> Result<Self, Self::Error> {
-        let magic = PcapMagic::try_from(header.magic_num)?;
+        let magic = PcapMagic::try_from(header.magic_num).or(Err(PcapError::BadMagic(header.magic_num)))?;
 
         if magic.need_byte_swap() {
             header.switch_endianness();
         }
 
-        let snaplen = usize::try_from(header.snaplen).or(Err(()))?;
+        let snaplen = usize::try_from(header.snaplen)
+            .or(Err(PcapError::SnaplenTooLarge { snaplen: header.snaplen, max: usize::MAX }))?;
 
         // docs say this version number hasn't changed since 1998, so this simplistic comparison
         // should suffice
@@ -99,20 +101,80 @@ impl TryFrom<PcapFileHeaderInFile> for PcapFileHeader {
                need_byte_swap: magic.need_byte_swap(),
                network: header.network,
                utc_offset: header.thiszone,
+               sigfigs: header.sigfigs,
                snaplen,
+               variant: magic.variant(),
             })
         } else {
-            Err(())
+            Err(PcapError::UnsupportedVersion { major: header.version_major, minor: header.version_minor })
         }
     }
 }
+impl PcapFileHeader {
+    /// The size of the serialized file header.
+    pub const LEN: u64 = mem::size_of::<PcapFileHeaderInFile>() as u64;
+
+    /// The size of a record header in the file, including the fields of extended records.
+    pub fn record_header_len(&self) -> u64 {
+        self.variant.record_header_len() as u64
+    }
+}
+
+
+/// The variants of the traditional pcap format, which are told apart by the magic number.
+#[derive(Copy,Clone,PartialEq,Eq,Debug,Default)]
+#[non_exhaustive]
+pub enum PcapVariant {
+    /// The standard format, written by libpcap.
+    #[default]
+    Standard,
+    /// The modified format by Alexey Kuznetzov, written by old versions of tcpdump patched by
+    /// Red Hat and SuSE. Every record has additional fields, see `ExtendedRecord`. Timestamps
+    /// always have microsecond resolution.
+    Modified,
+    /// The format written by IXIA hardware traffic generators. Timestamps always have nanosecond
+    /// resolution.
+    IxiaHardware,
+    /// The format written by IXIA software traffic generators. Timestamps always have nanosecond
+    /// resolution.
+    IxiaSoftware,
+    /// The format written by Nokia IPSO. Every record header has 4 additional bytes of unknown
+    /// meaning, which are skipped when reading and written as zeroes. Timestamps always have
+    /// microsecond resolution.
+    ///
+    /// Since the file header is the same as in the standard format, files of this variant can't be
+    /// detected. They have to be read with `ReaderOptions::nokia`, and can only be appended to
+    /// with `PcapWriter::append_unchecked`.
+    Nokia,
+}
+impl PcapVariant {
+    /// Do records in this variant have the additional fields of `ExtendedRecord`?
+    pub fn extended_records(self) -> bool {
+        self == PcapVariant::Modified
+    }
 
+    /// The size of a record header in this variant, including any additional fields.
+    pub fn record_header_len(self) -> usize {
+        let len = mem::size_of::<PcapRecordHeader>();
+        match self {
+            PcapVariant::Modified => len + mem::size_of::<PcapExtendedRecordHeader>(),
+            PcapVariant::Nokia => len + NOKIA_RECORD_PADDING,
+            _ => len,
+        }
+    }
+}
+/// The number of additional bytes in record headers of the Nokia variant.
+pub const NOKIA_RECORD_PADDING: usize = 4;
 
 /// The different magic numbers for PCAP files.
+///
+/// The magic number is stored in the first four bytes of a file. Interpreted as a `u32` in the
+/// native byte order of the machine, it tells the byte order, the timestamp format and the
+/// variant of the file.
 #[repr(u32)]
-#[allow(dead_code)]
-#[derive(Copy,Clone)]
-enum PcapMagic {
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+#[non_exhaustive]
+pub enum PcapMagic {
     /// same byte order as in memory, timestamps with microsecond resolution
     Normal = 0xa1b2_c3d4,
     /// same byte order as in memory, timestamps with nanosecond resolution
@@ -121,42 +183,85 @@ enum PcapMagic {
     ByteSwap = (Self::Normal as u32).swap_bytes(),
     /// different byte order than in memory, timestamps with nanosecond resolution
     NanoSecondResolutionByteSwap = (Self::NanoSecondResolution as u32).swap_bytes(),
+    /// modified format, same byte order as in memory
+    Modified = 0xa1b2_cd34,
+    /// modified format, different byte order than in memory
+    ModifiedByteSwap = (Self::Modified as u32).swap_bytes(),
+    /// IXIA hardware format, same byte order as in memory
+    IxiaHardware = 0x1c00_01ac,
+    /// IXIA hardware format, different byte order than in memory
+    IxiaHardwareByteSwap = (Self::IxiaHardware as u32).swap_bytes(),
+    /// IXIA software format, same byte order as in memory
+    IxiaSoftware = 0x1c00_01ab,
+    /// IXIA software format, different byte order than in memory
+    IxiaSoftwareByteSwap = (Self::IxiaSoftware as u32).swap_bytes(),
 }
+/// All magic numbers, for parsing.
+const PCAP_MAGICS: [PcapMagic; 10] = [
+    PcapMagic::Normal, PcapMagic::NanoSecondResolution, PcapMagic::ByteSwap,
+    PcapMagic::NanoSecondResolutionByteSwap, PcapMagic::Modified, PcapMagic::ModifiedByteSwap,
+    PcapMagic::IxiaHardware, PcapMagic::IxiaHardwareByteSwap, PcapMagic::IxiaSoftware,
+    PcapMagic::IxiaSoftwareByteSwap,
+];
 impl TryFrom<u32> for PcapMagic {
     type Error = ();
 
     /// Try to convert a `u32` to a `PcapMagic`.
     fn try_from(val: u32) -> Result<Self, Self::Error> {
-        if val == PcapMagic::Normal.into() {
-            Ok(PcapMagic::Normal)
-        } else if val == PcapMagic::NanoSecondResolution.into() {
-            Ok(PcapMagic::NanoSecondResolution)
-        } else if val == PcapMagic::ByteSwap.into() {
-            Ok(PcapMagic::ByteSwap)
-        } else if val == PcapMagic::NanoSecondResolutionByteSwap.into() {
-            Ok(PcapMagic::NanoSecondResolutionByteSwap)
-        } else {
-            Err(())
-        }
+        PCAP_MAGICS.iter().copied().find(|&magic| u32::from(magic) == val).ok_or(())
     }
 }
 impl PcapMagic {
-    /// Does the file use native endianess of our platform?
-    fn need_byte_swap(self) -> bool {
+    /// Returns the magic number in native byte order for a file of the `variant`, with timestamps
+    /// in nanosecond resolution if `ns_res` is set. Returns `None` if the variant doesn't support
+    /// that resolution.
+    pub fn new(variant: PcapVariant, ns_res: bool) -> Option<PcapMagic> {
+        match (variant, ns_res) {
+            (PcapVariant::Standard, false) => Some(PcapMagic::Normal),
+            (PcapVariant::Standard, true) => Some(PcapMagic::NanoSecondResolution),
+            (PcapVariant::Modified, false) => Some(PcapMagic::Modified),
+            (PcapVariant::Nokia, false) => Some(PcapMagic::Normal),
+            (PcapVariant::IxiaHardware, true) => Some(PcapMagic::IxiaHardware),
+            (PcapVariant::IxiaSoftware, true) => Some(PcapMagic::IxiaSoftware),
+            _ => None,
+        }
+    }
+    /// Does the file use a different byte order than the native one of our platform?
+    pub fn need_byte_swap(self) -> bool {
         match self {
             PcapMagic::Normal |
-            PcapMagic::NanoSecondResolution => false,
+            PcapMagic::NanoSecondResolution |
+            PcapMagic::Modified |
+            PcapMagic::IxiaHardware |
+            PcapMagic::IxiaSoftware => false,
             PcapMagic::ByteSwap |
-            PcapMagic::NanoSecondResolutionByteSwap => true,
+            PcapMagic::NanoSecondResolutionByteSwap |
+            PcapMagic::ModifiedByteSwap |
+            PcapMagic::IxiaHardwareByteSwap |
+            PcapMagic::IxiaSoftwareByteSwap => true,
         }
     }
     /// Are timestamps in nanosecond resolution?
     /// true, if the timestamp has nanosecond resolution (as opposed to microsecond resolution)
-    fn ns_res(self) -> bool {
+    pub fn ns_res(self) -> bool {
         match self {
-            PcapMagic::Normal | PcapMagic::ByteSwap => false,
+            PcapMagic::Normal | PcapMagic::ByteSwap |
+            PcapMagic::Modified | PcapMagic::ModifiedByteSwap => false,
             PcapMagic::NanoSecondResolution |
-            PcapMagic::NanoSecondResolutionByteSwap => true,
+            PcapMagic::NanoSecondResolutionByteSwap |
+            PcapMagic::IxiaHardware | PcapMagic::IxiaHardwareByteSwap |
+            PcapMagic::IxiaSoftware | PcapMagic::IxiaSoftwareByteSwap => true,
+        }
+    }
+    /// The variant of the file format.
+    pub fn variant(self) -> PcapVariant {
+        match self {
+            PcapMagic::Normal | PcapMagic::ByteSwap |
+            PcapMagic::NanoSecondResolution |
+            PcapMagic::NanoSecondResolutionByteSwap => PcapVariant::Standard,
+            PcapMagic::Modified | PcapMagic::ModifiedByteSwap => PcapVariant::Modified,
+            PcapMagic::IxiaHardware | PcapMagic::IxiaHardwareByteSwap => PcapVariant::IxiaHardware,
+            PcapMagic::IxiaSoftware | PcapMagic::IxiaSoftwareByteSwap => PcapVariant::IxiaSoftware,
         }
     }
 }
@@ -187,156 +292,614 @@ impl Packed for PcapRecordHeader {
     }
 }
 
-#[cfg(feature = "time")]
-fn make_time(sec: i64, nsec: u32) -> Option<Time> {
-    Some(Time::new(sec, nsec.try_into().ok()?))
+/// Additional per-packet header data in captures of the modified format.
+#[repr(C,packed)]
+pub struct PcapExtendedRecordHeader {
+    /// index of the interface the packet was captured on
+    pub ifindex: i32,
+    /// protocol of the packet, always in big endian byte order
+    pub protocol: u16,
+    /// type of the packet
+    pub pkt_type: u8,
+    /// padding
+    pub pad: u8,
 }
-#[cfg(not(feature = "time"))]
-fn make_time(sec: i64, nsec: u32) -> Option<Time> {
-    UNIX_EPOCH.checked_add(Duration::new(sec.try_into().ok()?, nsec))
+impl Packed for PcapExtendedRecordHeader {
+    fn switch_endianness(&mut self) {
+        self.ifindex = self.ifindex.swap_bytes();
+    }
+}
+impl From<PcapExtendedRecordHeader> for ExtendedRecord {
+    fn from(header: PcapExtendedRecordHeader) -> ExtendedRecord {
+        ExtendedRecord {
+            ifindex: header.ifindex,
+            protocol: u16::from_be(header.protocol),
+            pkt_type: header.pkt_type,
+        }
+    }
+}
+impl From<ExtendedRecord> for PcapExtendedRecordHeader {
+    fn from(record: ExtendedRecord) -> PcapExtendedRecordHeader {
+        PcapExtendedRecordHeader {
+            ifindex: record.ifindex,
+            protocol: record.protocol.to_be(),
+            pkt_type: record.pkt_type,
+            pad: 0,
+        }
+    }
 }
 
 impl PcapRecordHeader {
     /// Get the time and date of this packet.
     pub fn get_time(&self, file_header: &PcapFileHeader) -> Option<Time> {
+        self.get_timestamp(file_header)?.to_time()
+    }
+
+    /// Get the time and date of this packet as a `Timestamp`. Unlike `get_time`, this also works
+    /// for timestamps that `Time` can't represent.
+    pub fn get_timestamp(&self, file_header: &PcapFileHeader) -> Option<Timestamp> {
         let nsec = if file_header.ns_res {
             self.ts_usec
         } else {
             self.ts_usec.checked_mul(1000)?
         };
-        let sec = i64::from(self.ts_sec).checked_add(i64::from(file_header.utc_offset))?;
+        Timestamp::new(i64::from(self.ts_sec), nsec)?
+            .checked_add_secs(i64::from(file_header.utc_offset))
+    }
+}
+
+
+/// Block type of the pcapng Section Header Block. The value is a palindrome, so it can be used as
+/// the magic number of pcapng files regardless of byte order.
+pub const NG_BLOCK_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+/// Block type of the pcapng Interface Description Block.
+pub const NG_BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+/// Block type of the obsolete pcapng Packet Block.
+pub const NG_BLOCK_PACKET: u32 = 0x0000_0002;
+/// Block type of the pcapng Simple Packet Block.
+pub const NG_BLOCK_SIMPLE_PACKET: u32 = 0x0000_0003;
+/// Block type of the pcapng Enhanced Packet Block.
+pub const NG_BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+
+/// The magic number of pcapng files, which is the block type of their first block.
+pub const PCAPNG_MAGIC: u32 = NG_BLOCK_SECTION_HEADER;
+
+/// The byte-order magic inside a pcapng Section Header Block.
+pub const NG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+/// only supported pcapng major version
+pub const NG_VERSION_MAJOR: u16 = 1;
+/// written pcapng minor version
+pub const NG_VERSION_MINOR: u16 = 0;
+
+/// pcapng option code that terminates an option list
+pub const NG_OPT_ENDOFOPT: u16 = 0;
+/// pcapng interface option with the timestamp resolution
+pub const NG_OPT_IF_TSRESOL: u16 = 9;
+/// pcapng interface option with the timestamp offset in seconds
+pub const NG_OPT_IF_TSOFFSET: u16 = 14;
+/// pcapng packet option with the link-layer flags
+pub const NG_OPT_EPB_FLAGS: u16 = 2;
+
+/// Size of the fixed part of a block: block type, total length and trailing total length.
+pub const NG_BLOCK_OVERHEAD: usize = 12;
 
-        if nsec < 1_000_000_000 {
-            make_time(sec, nsec)
+/// Read-only view on the body of a pcapng block, taking care of the section's byte order.
+pub struct NgBlockBody<'a> {
+    pub data: &'a [u8],
+    pub need_byte_swap: bool,
+}
+impl<'a> NgBlockBody<'a> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+    }
+    /// Reads a `u16` at `offset`.
+    pub fn u16(&self, offset: usize) -> Option<u16> {
+        let val = u16::from_ne_bytes(self.bytes(offset)?);
+        Some(if self.need_byte_swap { val.swap_bytes() } else { val })
+    }
+    /// Reads a `u32` at `offset`.
+    pub fn u32(&self, offset: usize) -> Option<u32> {
+        let val = u32::from_ne_bytes(self.bytes(offset)?);
+        Some(if self.need_byte_swap { val.swap_bytes() } else { val })
+    }
+    /// Reads a `u64` at `offset`.
+    pub fn u64(&self, offset: usize) -> Option<u64> {
+        let val = u64::from_ne_bytes(self.bytes(offset)?);
+        Some(if self.need_byte_swap { val.swap_bytes() } else { val })
+    }
+    /// Reads a timestamp that is split in a high and a low `u32` at `offset`.
+    pub fn timestamp(&self, offset: usize) -> Option<u64> {
+        let high = u64::from(self.u32(offset)?);
+        let low = u64::from(self.u32(offset + 4)?);
+        Some(high << 32 | low)
+    }
+    /// Returns `len` bytes starting at `offset`.
+    pub fn slice(&self, offset: usize, len: usize) -> Option<&'a [u8]> {
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+    /// Iterates over the options starting at `offset`, returning their code and value.
+    pub fn options(&self, offset: usize) -> NgOptions<'a> {
+        NgOptions {
+            body: NgBlockBody { data: self.data.get(offset..).unwrap_or_default(), need_byte_swap: self.need_byte_swap },
+        }
+    }
+}
+
+/// Iterator over the options of a pcapng block.
+pub struct NgOptions<'a> {
+    body: NgBlockBody<'a>,
+}
+impl<'a> Iterator for NgOptions<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let code = self.body.u16(0)?;
+        let len = usize::from(self.body.u16(2)?);
+        let value = self.body.slice(4, len)?;
+        if code == NG_OPT_ENDOFOPT {
+            return None;
+        }
+        self.body.data = self.body.data.get(4 + ng_padded(len)..).unwrap_or_default();
+        Some((code, value))
+    }
+}
+
+/// Builder for the fixed part of pcapng blocks, taking care of the section's byte order.
+pub struct NgBlockBuilder {
+    pub data: Vec<u8>,
+    pub need_byte_swap: bool,
+}
+impl NgBlockBuilder {
+    /// Creates a new, empty builder.
+    pub fn new(need_byte_swap: bool) -> NgBlockBuilder {
+        NgBlockBuilder { data: Vec::new(), need_byte_swap }
+    }
+    /// Appends a `u16`.
+    pub fn u16(&mut self, val: u16) -> &mut Self {
+        let val = if self.need_byte_swap { val.swap_bytes() } else { val };
+        self.data.extend_from_slice(&val.to_ne_bytes());
+        self
+    }
+    /// Appends a `u32`.
+    pub fn u32(&mut self, val: u32) -> &mut Self {
+        let val = if self.need_byte_swap { val.swap_bytes() } else { val };
+        self.data.extend_from_slice(&val.to_ne_bytes());
+        self
+    }
+    /// Appends a `u64`.
+    pub fn u64(&mut self, val: u64) -> &mut Self {
+        let val = if self.need_byte_swap { val.swap_bytes() } else { val };
+        self.data.extend_from_slice(&val.to_ne_bytes());
+        self
+    }
+    /// Appends a timestamp as a high and a low `u32`.
+    pub fn timestamp(&mut self, val: u64) -> &mut Self {
+        self.u32((val >> 32) as u32).u32(val as u32)
+    }
+    /// Appends an option with the given code and value, including padding.
+    pub fn option(&mut self, code: u16, value: &[u8]) -> &mut Self {
+        self.u16(code).u16(value.len() as u16);
+        self.data.extend_from_slice(value);
+        self.data.resize(ng_padded(self.data.len()), 0);
+        self
+    }
+    /// Appends the end-of-options marker.
+    pub fn end_options(&mut self) -> &mut Self {
+        self.u16(NG_OPT_ENDOFOPT).u16(0)
+    }
+}
+
+/// Rounds `len` up to the 32 bit alignment used in pcapng files.
+pub fn ng_padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Parses the body of a pcapng Interface Description Block.
+pub fn ng_parse_interface(body: &NgBlockBody) -> Option<InterfaceOptions> {
+    let mut iface = InterfaceOptions {
+        linktype: Linktype::from_raw(u32::from(body.u16(0)?)),
+        snaplen: usize::try_from(body.u32(4)?).ok()?,
+        ts_resolution: Resolution::default(),
+        ts_offset: 0,
+    };
+    for (code, value) in body.options(8) {
+        let value = NgBlockBody { data: value, need_byte_swap: body.need_byte_swap };
+        match code {
+            NG_OPT_IF_TSRESOL => iface.ts_resolution = Resolution::from_tsresol(*value.data.first()?),
+            NG_OPT_IF_TSOFFSET => iface.ts_offset = value.u64(0)? as i64,
+            _ => {},
+        }
+    }
+    iface.ts_resolution.units_per_sec()?;
+    Some(iface)
+}
+
+/// Convert a timestamp to a pcapng timestamp for a packet captured on `iface`, rounded to the
+/// interface's resolution. Also returns whether the timestamp had to be rounded.
+pub fn ng_make_timestamp(ts: Timestamp, iface: &InterfaceOptions, rounding: Rounding) -> Option<(u64, bool)> {
+    let converted = ts.checked_sub_secs(iface.ts_offset)?.to_units(iface.ts_resolution, rounding)?;
+    Some((u64::try_from(converted.units).ok()?, converted.lossy))
+}
+
+/// Convert a pcapng timestamp of a packet captured on `iface` to a `Time`.
+pub fn ng_get_time(ts: u64, iface: &InterfaceOptions) -> Option<Time> {
+    Timestamp::from_units(i128::from(ts), iface.ts_resolution)?
+        .checked_add_secs(iface.ts_offset)?
+        .to_time()
+}
+
+
+/// Generates the `Linktype` enum and its conversions from a list of registered link types, each
+/// with its number, the name of the corresponding `DLT_` constant, and a description.
+macro_rules! linktypes {
+    ($($name:ident = $value:literal, $dlt:literal, $description:literal;)*) => {
+        /// Known identifiers for the types of packets that might be captured in a `pcap` file.
+        /// This tells you how to interpret the packets you receive.
+        ///
+        /// Look at [tcpdump.org](http://www.tcpdump.org/linktypes.html) for the canonical list with
+        /// descriptions. The variants follow that list up to `DECT_NR` (301). Values that are not
+        /// in it, or were registered later, are represented by `Unknown`, so `Linktype::from_raw`
+        /// never loses information.
+        #[derive(Copy,Clone,Debug)]
+        #[non_exhaustive]
+        #[allow(non_camel_case_types,clippy::upper_case_acronyms)]
+        pub enum Linktype {
+            $(
+                #[doc = $description]
+                $name,
+            )*
+            /// A link type that is not in the registry (or not known to this version of the crate).
+            Unknown(u32),
+        }
+
+        impl Linktype {
+            /// Returns the link type with the number `value`, or `Unknown` if it is not a known
+            /// link type.
+            pub fn from_raw(value: u32) -> Linktype {
+                match value {
+                    $($value => Linktype::$name,)*
+                    _ => Linktype::Unknown(value),
+                }
+            }
+
+            /// The name, `DLT_` name and description of a known link type.
+            fn info(self) -> Option<(&'static str, &'static str, &'static str)> {
+                match Linktype::from_raw(self.into()) {
+                    $(Linktype::$name => Some((stringify!($name), $dlt, $description)),)*
+                    Linktype::Unknown(_) => None,
+                }
+            }
+        }
+
+        impl From<Linktype> for u32 {
+            fn from(val: Linktype) -> u32 {
+                match val {
+                    $(Linktype::$name => $value,)*
+                    Linktype::Unknown(value) => value,
+                }
+            }
+        }
+
+        impl FromStr for Linktype {
+            type Err = ();
+
+            /// Parses a link type from its number, its name (with or without the `LINKTYPE_`
+            /// prefix) or the name of its `DLT_` constant. Names are not case-sensitive.
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                if let Ok(value) = s.parse() {
+                    return Ok(Linktype::from_raw(value));
+                }
+                let upper = s.to_ascii_uppercase();
+                let name = upper.strip_prefix("LINKTYPE_").unwrap_or(&upper);
+                $(
+                    if name == stringify!($name) || upper == $dlt {
+                        return Ok(Linktype::$name);
+                    }
+                )*
+                Err(())
+            }
+        }
+    };
+}
+
+linktypes! {
+    NULL = 0, "DLT_NULL", "BSD loopback encapsulation";
+    ETHERNET = 1, "DLT_EN10MB", "IEEE 802.3 Ethernet";
+    AX25 = 3, "DLT_AX25", "AX.25 packets";
+    IEEE802_5 = 6, "DLT_IEEE802", "IEEE 802.5 Token Ring";
+    ARCNET_BSD = 7, "DLT_ARCNET", "ARCNET data packets, as described by RFC 1051";
+    SLIP = 8, "DLT_SLIP", "SLIP, with a pseudo-header";
+    PPP = 9, "DLT_PPP", "PPP, as described by RFC 1661 and RFC 1662";
+    FDDI = 10, "DLT_FDDI", "FDDI, as specified by ANSI INCITS 239-1994";
+    PPP_HDLC = 50, "DLT_PPP_SERIAL", "PPP in HDLC-like framing, as described by RFC 1662";
+    PPP_ETHER = 51, "DLT_PPP_ETHER", "PPPoE session packets";
+    ATM_RFC1483 = 100, "DLT_ATM_RFC1483", "RFC 1483 LLC/SNAP-encapsulated ATM";
+    RAW = 101, "DLT_RAW", "IP packets (IPv4 or IPv6)";
+    C_HDLC = 104, "DLT_C_HDLC", "Cisco PPP with HDLC framing";
+    IEEE802_11 = 105, "DLT_IEEE802_11", "IEEE 802.11 wireless LAN";
+    FRELAY = 107, "DLT_FRELAY", "Frame Relay LAPF frames";
+    LOOP = 108, "DLT_LOOP", "OpenBSD loopback encapsulation";
+    LINUX_SLL = 113, "DLT_LINUX_SLL", "Linux \"cooked\" capture encapsulation";
+    LTALK = 114, "DLT_LTALK", "Apple LocalTalk";
+    PFLOG = 117, "DLT_PFLOG", "OpenBSD pflog";
+    IEEE802_11_PRISM = 119, "DLT_PRISM_HEADER", "IEEE 802.11, with a Prism monitor mode header";
+    IP_OVER_FC = 122, "DLT_IP_OVER_FC", "IP-over-Fibre Channel, as described by RFC 2625";
+    SUNATM = 123, "DLT_SUNATM", "ATM traffic, as captured by SunATM";
+    IEEE802_11_RADIOTAP = 127, "DLT_IEEE802_11_RADIO", "IEEE 802.11, with a radiotap header";
+    ARCNET_LINUX = 129, "DLT_ARCNET_LINUX", "ARCNET data packets, with a Linux header";
+    APPLE_IP_OVER_IEEE1394 = 138, "DLT_APPLE_IP_OVER_IEEE1394", "Apple IP-over-IEEE 1394";
+    MTP2_WITH_PHDR = 139, "DLT_MTP2_WITH_PHDR", "SS7 MTP2, with a pseudo-header";
+    MTP2 = 140, "DLT_MTP2", "SS7 MTP2";
+    MTP3 = 141, "DLT_MTP3", "SS7 MTP3, without an MTP2 header";
+    SCCP = 142, "DLT_SCCP", "SS7 SCCP, without an MTP3 or MTP2 header";
+    DOCSIS = 143, "DLT_DOCSIS", "DOCSIS MAC frames";
+    LINUX_IRDA = 144, "DLT_LINUX_IRDA", "Linux-IrDA packets";
+    IBM_SP = 145, "DLT_IBM_SP", "Reserved for IBM SP switch";
+    IBM_SN = 146, "DLT_IBM_SN", "Reserved for IBM Next Federation switch";
+    USER0 = 147, "DLT_USER0", "Reserved for private use";
+    USER1 = 148, "DLT_USER1", "Reserved for private use";
+    USER2 = 149, "DLT_USER2", "Reserved for private use";
+    USER3 = 150, "DLT_USER3", "Reserved for private use";
+    USER4 = 151, "DLT_USER4", "Reserved for private use";
+    USER5 = 152, "DLT_USER5", "Reserved for private use";
+    USER6 = 153, "DLT_USER6", "Reserved for private use";
+    USER7 = 154, "DLT_USER7", "Reserved for private use";
+    USER8 = 155, "DLT_USER8", "Reserved for private use";
+    USER9 = 156, "DLT_USER9", "Reserved for private use";
+    USER10 = 157, "DLT_USER10", "Reserved for private use";
+    USER11 = 158, "DLT_USER11", "Reserved for private use";
+    USER12 = 159, "DLT_USER12", "Reserved for private use";
+    USER13 = 160, "DLT_USER13", "Reserved for private use";
+    USER14 = 161, "DLT_USER14", "Reserved for private use";
+    USER15 = 162, "DLT_USER15", "Reserved for private use";
+    IEEE802_11_AVS = 163, "DLT_IEEE802_11_RADIO_AVS", "IEEE 802.11, with an AVS monitor mode header";
+    BACNET_MS_TP = 165, "DLT_BACNET_MS_TP", "BACnet MS/TP frames";
+    PPP_PPPD = 166, "DLT_PPP_PPPD", "PPP in HDLC-like encapsulation, with a direction byte";
+    GPRS_LLC = 169, "DLT_GPRS_LLC", "GPRS Logical Link Control";
+    GPF_T = 170, "DLT_GPF_T", "Transparent-mapped generic framing procedure";
+    GPF_F = 171, "DLT_GPF_F", "Frame-mapped generic framing procedure";
+    LINUX_LAPD = 177, "DLT_LINUX_LAPD", "LAPD frames, with a Linux LAPD header";
+    MFR = 182, "DLT_MFR", "FRF.16.1 Multi-Link Frame Relay frames";
+    BLUETOOTH_HCI_H4 = 187, "DLT_BLUETOOTH_HCI_H4", "Bluetooth HCI UART transport layer";
+    USB_LINUX = 189, "DLT_USB_LINUX", "USB packets, with a Linux USB header";
+    IEEE802_15_4_LINUX = 191, "DLT_IEEE802_15_4_LINUX", "IEEE 802.15.4, with the address fields padded, as done by Linux drivers";
+    PPI = 192, "DLT_PPI", "Per-Packet Information header, followed by packet data";
+    IEEE802_15_4_WITHFCS = 195, "DLT_IEEE802_15_4_WITHFCS", "IEEE 802.15.4, with FCS";
+    SITA = 196, "DLT_SITA", "Various link-layer types, with a SITA pseudo-header";
+    ERF = 197, "DLT_ERF", "Various link-layer types, with an Endace ERF header";
+    IPMB_KONTRON = 199, "DLT_IPMB_KONTRON", "IPMB packets, with a Kontron pseudo-header";
+    BLUETOOTH_HCI_H4_WITH_PHDR = 201, "DLT_BLUETOOTH_HCI_H4_WITH_PHDR", "Bluetooth HCI UART transport layer, with a direction pseudo-header";
+    AX25_KISS = 202, "DLT_AX25_KISS", "AX.25 packets, with a KISS header";
+    LAPD = 203, "DLT_LAPD", "LAPD frames";
+    PPP_WITH_DIR = 204, "DLT_PPP_WITH_DIR", "PPP, with a direction pseudo-header";
+    C_HDLC_WITH_DIR = 205, "DLT_C_HDLC_WITH_DIR", "Cisco PPP with HDLC framing, with a direction pseudo-header";
+    FRELAY_WITH_DIR = 206, "DLT_FRELAY_WITH_DIR", "Frame Relay LAPF frames, with a direction pseudo-header";
+    LAPB_WITH_DIR = 207, "DLT_LAPB_WITH_DIR", "LAPB frames, with a direction pseudo-header";
+    IPMB_LINUX = 209, "DLT_IPMB_LINUX", "IPMB over I2C, with a Linux pseudo-header";
+    FLEXRAY = 210, "DLT_FLEXRAY", "FlexRay frames or symbols, with a pseudo-header";
+    LIN = 212, "DLT_LIN", "LIN frames, with a pseudo-header";
+    IEEE802_15_4_NONASK_PHY = 215, "DLT_IEEE802_15_4_NONASK_PHY", "IEEE 802.15.4, with PHY-level data for non-ASK PHYs";
+    USB_LINUX_MMAPPED = 220, "DLT_USB_LINUX_MMAPPED", "USB packets, with a Linux USB header for memory-mapped captures";
+    FC_2 = 224, "DLT_FC_2", "Fibre Channel FC-2 frames";
+    FC_2_WITH_FRAME_DELIMS = 225, "DLT_FC_2_WITH_FRAME_DELIMS", "Fibre Channel FC-2 frames, with frame delimiters";
+    IPNET = 226, "DLT_IPNET", "Solaris ipnet pseudo-header";
+    CAN_SOCKETCAN = 227, "DLT_CAN_SOCKETCAN", "CAN frames, with a Linux SocketCAN pseudo-header";
+    IPV4 = 228, "DLT_IPV4", "IPv4 packets";
+    IPV6 = 229, "DLT_IPV6", "IPv6 packets";
+    IEEE802_15_4_NOFCS = 230, "DLT_IEEE802_15_4_NOFCS", "IEEE 802.15.4, without FCS";
+    DBUS = 231, "DLT_DBUS", "D-Bus messages";
+    DVB_CI = 235, "DLT_DVB_CI", "DVB-CI messages, with a pseudo-header";
+    MUX27010 = 236, "DLT_MUX27010", "3GPP TS 27.010 multiplexing protocol variant";
+    STANAG_5066_D_PDU = 237, "DLT_STANAG_5066_D_PDU", "STANAG 5066 D_PDUs";
+    NFLOG = 239, "DLT_NFLOG", "Linux netfilter NFLOG messages";
+    NETANALYZER = 240, "DLT_NETANALYZER", "Ethernet frames, with a Hilscher netANALYZER pseudo-header";
+    NETANALYZER_TRANSPARENT = 241, "DLT_NETANALYZER_TRANSPARENT", "Ethernet frames with preamble and SFD, with a Hilscher netANALYZER pseudo-header";
+    IPOIB = 242, "DLT_IPOIB", "IP-over-InfiniBand";
+    MPEG_2_TS = 243, "DLT_MPEG_2_TS", "MPEG-2 transport stream packets";
+    NG40 = 244, "DLT_NG40", "ng4T ng40 protocol tester frames";
+    NFC_LLCP = 245, "DLT_NFC_LLCP", "NFC LLCP frames, with a pseudo-header";
+    PFSYNC = 246, "DLT_PFSYNC", "OpenBSD pfsync state synchronisation messages";
+    INFINIBAND = 247, "DLT_INFINIBAND", "Raw InfiniBand frames";
+    SCTP = 248, "DLT_SCTP", "SCTP packets, without lower-level protocols";
+    USBPCAP = 249, "DLT_USBPCAP", "USB packets, with a USBPcap header";
+    RTAC_SERIAL = 250, "DLT_RTAC_SERIAL", "Serial line packets from the SEL RTAC product";
+    BLUETOOTH_LE_LL = 251, "DLT_BLUETOOTH_LE_LL", "Bluetooth Low Energy link layer packets";
+    WIRESHARK_UPPER_PDU = 252, "DLT_WIRESHARK_UPPER_PDU", "Upper-layer protocol PDUs, with a Wireshark pseudo-header";
+    NETLINK = 253, "DLT_NETLINK", "Linux netlink messages";
+    BLUETOOTH_LINUX_MONITOR = 254, "DLT_BLUETOOTH_LINUX_MONITOR", "Bluetooth Linux monitor encapsulation";
+    BLUETOOTH_BREDR_BB = 255, "DLT_BLUETOOTH_BREDR_BB", "Bluetooth Basic Rate and Enhanced Data Rate baseband packets";
+    BLUETOOTH_LE_LL_WITH_PHDR = 256, "DLT_BLUETOOTH_LE_LL_WITH_PHDR", "Bluetooth Low Energy link layer packets, with a pseudo-header";
+    PROFIBUS_DL = 257, "DLT_PROFIBUS_DL", "PROFIBUS data link layer packets";
+    PKTAP = 258, "DLT_PKTAP", "Apple PKTAP capture encapsulation";
+    EPON = 259, "DLT_EPON", "Ethernet-over-passive-optical-network packets";
+    IPMI_HPM_2 = 260, "DLT_IPMI_HPM_2", "IPMI trace packets";
+    ZWAVE_R1_R2 = 261, "DLT_ZWAVE_R1_R2", "Z-Wave RF profile R1 and R2 packets";
+    ZWAVE_R3 = 262, "DLT_ZWAVE_R3", "Z-Wave RF profile R3 packets";
+    WATTSTOPPER_DLM = 263, "DLT_WATTSTOPPER_DLM", "WattStopper Digital Lighting Management packets";
+    ISO_14443 = 264, "DLT_ISO_14443", "ISO 14443 contactless smartcard messages";
+    RDS = 265, "DLT_RDS", "Radio data system group data";
+    USB_DARWIN = 266, "DLT_USB_DARWIN", "USB packets, as captured on Darwin";
+    SDLC = 268, "DLT_SDLC", "SDLC frames";
+    LORATAP = 270, "DLT_LORATAP", "LoRa packets, with a LoRaTap pseudo-header";
+    VSOCK = 271, "DLT_VSOCK", "Host to guest communication in VMware and KVM hypervisors";
+    NORDIC_BLE = 272, "DLT_NORDIC_BLE", "Messages of a Nordic Semiconductor nRF Sniffer for Bluetooth LE";
+    DOCSIS31_XRA31 = 273, "DLT_DOCSIS31_XRA31", "DOCSIS packets and bursts, with an Excentis XRA-31 pseudo-header";
+    ETHERNET_MPACKET = 274, "DLT_ETHERNET_MPACKET", "IEEE 802.3br mPackets";
+    DISPLAYPORT_AUX = 275, "DLT_DISPLAYPORT_AUX", "DisplayPort AUX channel monitoring data";
+    LINUX_SLL2 = 276, "DLT_LINUX_SLL2", "Linux \"cooked\" capture encapsulation v2";
+    OPENVIZSLA = 278, "DLT_OPENVIZSLA", "OpenVizsla USB sniffer frames";
+    EBHSCR = 279, "DLT_EBHSCR", "Elektrobit High Speed Capture and Replay frames";
+    VPP_DISPATCH = 280, "DLT_VPP_DISPATCH", "fd.io VPP graph dispatch tracer records";
+    DSA_TAG_BRCM = 281, "DLT_DSA_TAG_BRCM", "Ethernet frames, with a Broadcom switch tag";
+    DSA_TAG_BRCM_PREPEND = 282, "DLT_DSA_TAG_BRCM_PREPEND", "Ethernet frames, with a prepended Broadcom switch tag";
+    IEEE802_15_4_TAP = 283, "DLT_IEEE802_15_4_TAP", "IEEE 802.15.4, with a TAP pseudo-header";
+    DSA_TAG_DSA = 284, "DLT_DSA_TAG_DSA", "Ethernet frames, with a Marvell DSA switch tag";
+    DSA_TAG_EDSA = 285, "DLT_DSA_TAG_EDSA", "Ethernet frames, with a Marvell EDSA switch tag";
+    ELEE = 286, "DLT_ELEE", "ELEE lawful intercept packets";
+    Z_WAVE_SERIAL = 287, "DLT_Z_WAVE_SERIAL", "Serial frames between a host and a Z-Wave chip";
+    USB_2_0 = 288, "DLT_USB_2_0", "USB 2.0, 1.1 or 1.0 packets";
+    ATSC_ALP = 289, "DLT_ATSC_ALP", "ATSC link-layer protocol frames";
+    ETW = 290, "DLT_ETW", "Event Tracing for Windows messages";
+    NETANALYZER_NG = 291, "DLT_NETANALYZER_NG", "Ethernet frames and other data, with a Hilscher netANALYZER NG pseudo-footer";
+    ZBOSS_NCP = 292, "DLT_ZBOSS_NCP", "ZBOSS Zigbee stack serial NCP protocol";
+    USB_2_0_LOW_SPEED = 293, "DLT_USB_2_0_LOW_SPEED", "Low-speed USB 2.0, 1.1 or 1.0 packets";
+    USB_2_0_FULL_SPEED = 294, "DLT_USB_2_0_FULL_SPEED", "Full-speed USB 2.0, 1.1 or 1.0 packets";
+    USB_2_0_HIGH_SPEED = 295, "DLT_USB_2_0_HIGH_SPEED", "High-speed USB 2.0 packets";
+    AUERSWALD_LOG = 296, "DLT_AUERSWALD_LOG", "Auerswald logger protocol packets";
+    ZWAVE_TAP = 297, "DLT_ZWAVE_TAP", "Z-Wave packets, with a TAP meta-data header";
+    SILABS_DEBUG_CHANNEL = 298, "DLT_SILABS_DEBUG_CHANNEL", "Silicon Labs debug channel protocol";
+    FIRA_UCI = 299, "DLT_FIRA_UCI", "Ultra-wideband controller interface protocol";
+    MDB = 300, "DLT_MDB", "MDB protocol between a vending machine controller and peripherals";
+    DECT_NR = 301, "DLT_DECT_NR", "DECT-2020 New Radio MAC layer";
+}
+
+impl Linktype {
+    #[deprecated(note = "renamed to `USER0`")]
+    pub const USER00_LINKTYPE: Linktype = Linktype::USER0;
+    #[deprecated(note = "renamed to `USER1`")]
+    pub const USER01_LINKTYPE: Linktype = Linktype::USER1;
+    #[deprecated(note = "renamed to `USER2`")]
+    pub const USER02_LINKTYPE: Linktype = Linktype::USER2;
+    #[deprecated(note = "renamed to `USER3`")]
+    pub const USER03_LINKTYPE: Linktype = Linktype::USER3;
+    #[deprecated(note = "renamed to `USER4`")]
+    pub const USER04_LINKTYPE: Linktype = Linktype::USER4;
+    #[deprecated(note = "renamed to `USER5`")]
+    pub const USER05_LINKTYPE: Linktype = Linktype::USER5;
+    #[deprecated(note = "renamed to `USER6`")]
+    pub const USER06_LINKTYPE: Linktype = Linktype::USER6;
+    #[deprecated(note = "renamed to `USER7`")]
+    pub const USER07_LINKTYPE: Linktype = Linktype::USER7;
+    #[deprecated(note = "renamed to `USER8`")]
+    pub const USER08_LINKTYPE: Linktype = Linktype::USER8;
+    #[deprecated(note = "renamed to `USER9`")]
+    pub const USER09_LINKTYPE: Linktype = Linktype::USER9;
+    #[deprecated(note = "renamed to `USER10`")]
+    pub const USER10_LINKTYPE: Linktype = Linktype::USER10;
+    #[deprecated(note = "renamed to `USER11`")]
+    pub const USER11_LINKTYPE: Linktype = Linktype::USER11;
+    #[deprecated(note = "renamed to `USER12`")]
+    pub const USER12_LINKTYPE: Linktype = Linktype::USER12;
+    #[deprecated(note = "renamed to `USER13`")]
+    pub const USER13_LINKTYPE: Linktype = Linktype::USER13;
+    #[deprecated(note = "renamed to `USER14`")]
+    pub const USER14_LINKTYPE: Linktype = Linktype::USER14;
+    #[deprecated(note = "renamed to `USER15`")]
+    pub const USER15_LINKTYPE: Linktype = Linktype::USER15;
+    #[deprecated(note = "renamed to `IEEE802_15_4_WITHFCS`")]
+    pub const IEEE802_15_4: Linktype = Linktype::IEEE802_15_4_WITHFCS;
+
+    /// The name of the link type in the registry, without the `LINKTYPE_` prefix. Returns `None`
+    /// for unknown link types.
+    pub fn name(self) -> Option<&'static str> {
+        self.info().map(|(name, _, _)| name)
+    }
+
+    /// The name of the `DLT_` constant that libpcap uses for this link type. Returns `None` for
+    /// unknown link types.
+    pub fn dlt_name(self) -> Option<&'static str> {
+        self.info().map(|(_, dlt, _)| dlt)
+    }
+
+    /// A short description of the link type. Returns `None` for unknown link types.
+    pub fn description(self) -> Option<&'static str> {
+        self.info().map(|(_, _, description)| description)
+    }
+}
+impl TryFrom<u32> for Linktype {
+    type Error = ();
+
+    /// Returns the link type with the number `value`, or an error if it is not a known link type.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match Linktype::from_raw(value) {
+            Linktype::Unknown(_) => Err(()),
+            linktype => Ok(linktype),
+        }
+    }
+}
+impl PartialEq for Linktype {
+    fn eq(&self, other: &Linktype) -> bool {
+        u32::from(*self) == u32::from(*other)
+    }
+}
+impl Eq for Linktype {}
+impl Hash for Linktype {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        u32::from(*self).hash(state)
+    }
+}
+impl fmt::Display for Linktype {
+    /// Writes the name of the link type, or its number if it is unknown.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", u32::from(*self)),
+        }
+    }
+}
+
+/// The bits of the `network` field in the file header that contain the link type.
+const NETWORK_LINKTYPE_MASK: u32 = 0x0000_ffff;
+/// The bit of the `network` field that is set if the FCS length is present.
+const NETWORK_FCS_PRESENT: u32 = 0x0400_0000;
+/// The position of the FCS length in the `network` field.
+const NETWORK_FCS_SHIFT: u32 = 28;
+/// The bits of the `network` field that contain the FCS length, if it is present.
+const NETWORK_FCS_MASK: u32 = 0xf000_0000;
+/// The bits of the `network` field that are reserved.
+pub const NETWORK_RESERVED_MASK: u32 = 0x0bff_0000;
+
+/// The length of the frame check sequence (FCS) at the end of every packet in a capture.
+///
+/// Captures in the traditional format can store it in the upper bits of the link type field of
+/// the file header, in units of 16 bits.
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub struct FcsLength(u8);
+impl FcsLength {
+    /// Creates an FCS length of `bytes` bytes. Returns `None` if the length can't be stored in a
+    /// file header, i.e. if it is odd or larger than 30 bytes.
+    // `is_multiple_of` would need Rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn from_bytes(bytes: usize) -> Option<FcsLength> {
+        if bytes % 2 == 0 && bytes <= 30 {
+            Some(FcsLength((bytes / 2) as u8))
         } else {
             None
         }
     }
+
+    /// The length of the FCS in bytes.
+    pub fn bytes(self) -> usize {
+        usize::from(self.0) * 2
+    }
 }
 
+/// Splits the `network` field of the file header into the link type, the FCS length, and the
+/// reserved bits. If no FCS length is present, the bits that would contain it are returned with
+/// the reserved bits, so that nothing is lost.
+pub fn split_network(network: u32) -> (Linktype, Option<FcsLength>, u32) {
+    let linktype = Linktype::from_raw(network & NETWORK_LINKTYPE_MASK);
+    if network & NETWORK_FCS_PRESENT != 0 {
+        let fcs_length = FcsLength((network >> NETWORK_FCS_SHIFT) as u8);
+        (linktype, Some(fcs_length), network & NETWORK_RESERVED_MASK)
+    } else {
+        (linktype, None, network & (NETWORK_RESERVED_MASK | NETWORK_FCS_MASK))
+    }
+}
 
-/// Known identifiers for the types of packets that might be captured in a `pcap` file. This tells
-/// you how to interpret the packets you receive.
-///
-/// Look at [tcpdump.org](http://www.tcpdump.org/linktypes.html) for the canonical list with
-/// descriptions.
-#[derive(Copy,Clone)]
-#[repr(u32)]
-#[allow(dead_code,non_camel_case_types,clippy::upper_case_acronyms)]
-pub enum Linktype {
-    NULL = 0,
-    /// Ethernet packets
-    ETHERNET = 1,
-    AX25 = 3,
-    IEEE802_5 = 6,
-    ARCNET_BSD = 7,
-    SLIP = 8,
-    PPP = 9,
-    FDDI = 10,
-    PPP_HDLC = 50,
-    PPP_ETHER = 51,
-    ATM_RFC1483 = 100,
-    /// IP packets (IPv4 or IPv6)
-    RAW = 101,
-    C_HDLC = 104,
-    IEEE802_11 = 105,
-    FRELAY = 107,
-    LOOP = 108,
-    LINUX_SLL = 113,
-    LTALK = 114,
-    PFLOG = 117,
-    IEEE802_11_PRISM = 119,
-    IP_OVER_FC = 122,
-    SUNATM = 123,
-    IEEE802_11_RADIOTAP = 127,
-    ARCNET_LINUX = 129,
-    APPLE_IP_OVER_IEEE1394 = 138,
-    MTP2_WITH_PHDR = 139,
-    MTP2 = 140,
-    MTP3 = 141,
-    SCCP = 142,
-    DOCSIS = 143,
-    LINUX_IRDA = 144,
-    USER00_LINKTYPE = 147,
-    USER01_LINKTYPE = 148,
-    USER02_LINKTYPE = 149,
-    USER03_LINKTYPE = 150,
-    USER04_LINKTYPE = 151,
-    USER05_LINKTYPE = 152,
-    USER06_LINKTYPE = 153,
-    USER07_LINKTYPE = 154,
-    USER08_LINKTYPE = 155,
-    USER09_LINKTYPE = 156,
-    USER10_LINKTYPE = 157,
-    USER11_LINKTYPE = 158,
-    USER12_LINKTYPE = 159,
-    USER13_LINKTYPE = 160,
-    USER14_LINKTYPE = 161,
-    USER15_LINKTYPE = 162,
-    IEEE802_11_AVS = 163,
-    BACNET_MS_TP = 165,
-    PPP_PPPD = 166,
-    GPRS_LLC = 169,
-    GPF_T = 170,
-    GPF_F = 171,
-    LINUX_LAPD = 177,
-    BLUETOOTH_HCI_H4 = 187,
-    USB_LINUX = 189,
-    PPI = 192,
-    IEEE802_15_4 = 195,
-    SITA = 196,
-    ERF = 197,
-    BLUETOOTH_HCI_H4_WITH_PHDR = 201,
-    AX25_KISS = 202,
-    LAPD = 203,
-    PPP_WITH_DIR = 204,
-    C_HDLC_WITH_DIR = 205,
-    FRELAY_WITH_DIR = 206,
-    IPMB_LINUX = 209,
-    IEEE802_15_4_NONASK_PHY = 215,
-    USB_LINUX_MMAPPED = 220,
-    FC_2 = 224,
-    FC_2_WITH_FRAME_DELIMS = 225,
-    IPNET = 226,
-    CAN_SOCKETCAN = 227,
-    IPV4 = 228,
-    IPV6 = 229,
-    IEEE802_15_4_NOFCS = 230,
-    DBUS = 231,
-    DVB_CI = 235,
-    MUX27010 = 236,
-    STANAG_5066_D_PDU = 237,
-    NFLOG = 239,
-    NETANALYZER = 240,
-    NETANALYZER_TRANSPARENT = 241,
-    IPOIB = 242,
-    MPEG_2_TS = 243,
-    NG40 = 244,
-    NFC_LLCP = 245,
-    INFINIBAND = 247,
-    SCTP = 248,
-    USBPCAP = 249,
-    RTAC_SERIAL = 250,
-    BLUETOOTH_LE_LL = 251,
-    NETLINK = 253,
-    BLUETOOTH_LINUX_MONITOR = 254,
-    BLUETOOTH_BREDR_BB = 255,
-    BLUETOOTH_LE_LL_WITH_PHDR = 256,
-    PROFIBUS_DL = 257,
-    PKTAP = 258,
-    EPON = 259,
-    IPMI_HPM_2 = 260,
-    ZWAVE_R1_R2 = 261,
-    ZWAVE_R3 = 262,
-    WATTSTOPPER_DLM = 263,
-    ISO_14443 = 264,
-    RDS = 265,
-    USB_DARWIN = 266,
-}
-impl From<Linktype> for u32 {
-    fn from(val: Linktype) -> u32 {
-        val as u32
+/// Combines the parts of the `network` field of the file header again. Returns `None` if any of
+/// them doesn't fit into its bits.
+pub fn join_network(linktype: Linktype, fcs_length: Option<FcsLength>, reserved: u32) -> Option<u32> {
+    let linktype = u32::from(linktype);
+    let reserved_mask = match fcs_length {
+        Some(_) => NETWORK_RESERVED_MASK,
+        None => NETWORK_RESERVED_MASK | NETWORK_FCS_MASK,
+    };
+    if linktype & !NETWORK_LINKTYPE_MASK != 0 || reserved & !reserved_mask != 0 {
+        return None;
     }
+    let fcs = fcs_length.map_or(0, |fcs| NETWORK_FCS_PRESENT | u32::from(fcs.0) << NETWORK_FCS_SHIFT);
+    Some(linktype | fcs | reserved)
 }
 #[cfg(test)]
 mod test {
@@ -348,5 +911,54 @@ mod test {
         // that the struct layouts are correct.
         assert_eq!(::std::mem::size_of::<PcapFileHeaderInFile>(), 24);
         assert_eq!(::std::mem::size_of::<PcapRecordHeader>(), 16);
+        assert_eq!(::std::mem::size_of::<PcapExtendedRecordHeader>(), 8);
+    }
+
+    #[test]
+    fn network_field() {
+        assert_eq!(split_network(1), (Linktype::ETHERNET, None, 0));
+        assert_eq!(split_network(0x2400_0001), (Linktype::ETHERNET, FcsLength::from_bytes(4), 0));
+        assert_eq!(split_network(0x0801_0069), (Linktype::IEEE802_11, None, 0x0801_0000));
+
+        assert_eq!(split_network(0x2000_0001), (Linktype::ETHERNET, None, 0x2000_0000));
+
+        for &network in &[0u32, 1, 0x2400_0001, 0xf400_ffff, 0x0400_0001, 0x0bff_0069, 0x2000_0001, 0xfbff_ffff] {
+            let (linktype, fcs_length, reserved) = split_network(network);
+            assert_eq!(join_network(linktype, fcs_length, reserved), Some(network));
+        }
+        assert_eq!(join_network(Linktype::Unknown(0x1_0000), None, 0), None);
+        assert_eq!(join_network(Linktype::ETHERNET, FcsLength::from_bytes(4), 0x1000_0000), None);
+        assert_eq!(join_network(Linktype::ETHERNET, None, 0x0400_0000), None);
+
+        assert_eq!(FcsLength::from_bytes(4).map(FcsLength::bytes), Some(4));
+        assert_eq!(FcsLength::from_bytes(3), None);
+        assert_eq!(FcsLength::from_bytes(32), None);
+    }
+
+    #[test]
+    fn linktype_conversions() {
+        assert_eq!(Linktype::try_from(1), Ok(Linktype::ETHERNET));
+        assert_eq!(Linktype::try_from(276), Ok(Linktype::LINUX_SLL2));
+        assert_eq!(Linktype::try_from(2), Err(()));
+        assert_eq!(Linktype::from_raw(2), Linktype::Unknown(2));
+        assert_eq!(Linktype::from_raw(246), Linktype::PFSYNC);
+        assert_eq!(Linktype::from_raw(291).name(), Some("NETANALYZER_NG"));
+        assert_eq!(Linktype::Unknown(1), Linktype::ETHERNET);
+        for value in 0..400 {
+            assert_eq!(u32::from(Linktype::from_raw(value)), value);
+        }
+
+        assert_eq!(Linktype::ETHERNET.name(), Some("ETHERNET"));
+        assert_eq!(Linktype::ETHERNET.dlt_name(), Some("DLT_EN10MB"));
+        assert_eq!(Linktype::Unknown(1).description(), Some("IEEE 802.3 Ethernet"));
+        assert_eq!(Linktype::Unknown(2).name(), None);
+
+        assert_eq!(Linktype::WIRESHARK_UPPER_PDU.to_string(), "WIRESHARK_UPPER_PDU");
+        assert_eq!(Linktype::Unknown(65000).to_string(), "65000");
+        for s in &["ETHERNET", "linktype_ethernet", "DLT_EN10MB", "1"] {
+            assert_eq!(s.parse(), Ok(Linktype::ETHERNET));
+        }
+        assert_eq!("65000".parse(), Ok(Linktype::Unknown(65000)));
+        assert_eq!("EN10MB".parse::<Linktype>(), Err(()));
     }
 }
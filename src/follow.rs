@@ -0,0 +1,357 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(all(feature = "inotify", target_os = "linux"))]
+use inotify::{Inotify, WatchMask};
+
+use super::def;
+use super::read::{read_file_header, read_record_header, PcapReader, ReaderOptions, RecordInfo};
+use super::CapturedPacket;
+use super::FileOptions;
+use super::PcapError;
+
+
+/// Decides how a `FollowReader` waits for new data once it has read everything that is available.
+pub trait WaitStrategy {
+    /// Blocks until the file may have changed. Returns `false` to stop following the file, which
+    /// makes `FollowReader::next` return `Ok(None)`.
+    fn wait(&mut self) -> io::Result<bool>;
+
+    /// Called whenever the `FollowReader` read new data, e.g. to reset a timeout.
+    fn activity(&mut self) {}
+}
+
+/// Waits by sleeping for a fixed interval, which works on all platforms and file systems.
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub struct Polling {
+    interval: Duration,
+    idle_timeout: Option<Duration>,
+    last_activity: Instant,
+}
+
+impl Polling {
+    /// Creates a strategy that checks for new data every `interval`, and never stops.
+    pub fn new(interval: Duration) -> Self {
+        Polling { interval, idle_timeout: None, last_activity: Instant::now() }
+    }
+
+    /// Stops following the file once no new data was written for `idle_timeout`.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+}
+
+impl Default for Polling {
+    /// Checks for new data every 200 milliseconds.
+    fn default() -> Self {
+        Polling::new(Duration::from_millis(200))
+    }
+}
+
+impl WaitStrategy for Polling {
+    fn wait(&mut self) -> io::Result<bool> {
+        if self.idle_timeout.is_some_and(|timeout| self.last_activity.elapsed() >= timeout) {
+            return Ok(false);
+        }
+        thread::sleep(self.interval);
+        Ok(true)
+    }
+
+    fn activity(&mut self) {
+        self.last_activity = Instant::now();
+    }
+}
+
+/// Waits for changes in the directory of the file with inotify, so new data is picked up
+/// immediately without polling. This requires the `inotify` feature, and is only available on
+/// Linux.
+#[cfg(all(feature = "inotify", target_os = "linux"))]
+pub struct InotifyWait {
+    inotify: Inotify,
+    buffer: Vec<u8>,
+}
+
+#[cfg(all(feature = "inotify", target_os = "linux"))]
+impl InotifyWait {
+    /// Creates a strategy that waits for changes to the file at `path`. The directory of the file
+    /// is watched, so that rotated files are noticed as well.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let dir = match path.as_ref().parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let inotify = Inotify::init()?;
+        inotify.watches().add(dir, WatchMask::MODIFY | WatchMask::CREATE | WatchMask::MOVED_TO |
+                                   WatchMask::MOVED_FROM | WatchMask::DELETE | WatchMask::CLOSE_WRITE)?;
+        Ok(InotifyWait { inotify, buffer: vec![0; 4096] })
+    }
+}
+
+#[cfg(all(feature = "inotify", target_os = "linux"))]
+impl WaitStrategy for InotifyWait {
+    fn wait(&mut self) -> io::Result<bool> {
+        // any event in the directory is a reason to check again
+        self.inotify.read_events_blocking(&mut self.buffer)?;
+        Ok(true)
+    }
+}
+
+
+/// Identifies a file independently of its path, to detect rotation.
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+#[cfg(not(unix))]
+fn file_id(_: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// The `FollowReader` struct reads packets from a capture file that is still being written, like
+/// `tail -f`. Once all available packets are read, it waits for more with a `WaitStrategy`.
+///
+/// If the file is truncated, or replaced by a new file at the same path (e.g. by log rotation),
+/// the reader starts over with the new file. On platforms without file identities (i.e. not
+/// Unix), only truncation is detected. Before data is read that was written since the file was
+/// last checked, the file header and the record header of the last packet are compared with the
+/// file, so that a file that was truncated and rewritten past the current position is noticed as
+/// well.
+///
+/// ```no_run
+/// use rpcap::follow::FollowReader;
+///
+/// let (_, mut reader) = FollowReader::open("live.pcap").unwrap();
+/// while let Some(packet) = reader.next().unwrap() {
+///     println!("{:?}: {} bytes", packet.time, packet.data.len());
+/// }
+/// ```
+pub struct FollowReader<W = Polling> {
+    path: PathBuf,
+    options: ReaderOptions,
+    wait: W,
+    reader: PcapReader<BufReader<File>>,
+    file_options: FileOptions,
+    file_id: Option<(u64, u64)>,
+    reopened: u64,
+    /// The last packet that was read, to check that it is still in the file.
+    last_record: Option<RecordInfo>,
+    /// The length and modification time of the file when it was last checked.
+    last_change: (u64, Option<SystemTime>),
+}
+
+impl FollowReader<Polling> {
+    /// Opens the capture file at `path` and follows it, checking for new data with the default
+    /// `Polling` strategy.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<(FileOptions, Self), PcapError> {
+        FollowReader::with_wait(path, ReaderOptions::default(), Polling::default())
+    }
+}
+
+impl<W: WaitStrategy> FollowReader<W> {
+    /// Opens the capture file at `path` and follows it, using the specified reader options and
+    /// wait strategy. Follow mode is always enabled in the reader options.
+    ///
+    /// If the file does not exist yet, or its file header is not complete yet, this waits for it.
+    pub fn with_wait<P: AsRef<Path>>(path: P, options: ReaderOptions, mut wait: W) -> Result<(FileOptions, Self), PcapError> {
+        let path = path.as_ref().to_path_buf();
+        let options = options.follow(true);
+        loop {
+            if let Some((file_options, reader, file_id)) = try_open(&path, options)? {
+                wait.activity();
+                let follow = FollowReader {
+                    path, options, wait, reader, file_options, file_id,
+                    reopened: 0,
+                    last_record: None,
+                    last_change: (0, None),
+                };
+                return Ok((file_options, follow));
+            }
+            if !wait.wait()? {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
+    /// Reads the next packet, waiting for it if necessary.
+    ///
+    /// Returns `Ok(None)` only when the wait strategy gives up.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<CapturedPacket<'_>>, PcapError> {
+        // the file may have been rewritten since it was last checked, which has to be noticed
+        // before reading data that wasn't in it back then
+        if self.reader.position() >= self.last_change.0 {
+            self.check_rotation()?;
+        }
+        while !self.reader.complete_record_available()? {
+            if !self.wait.wait()? {
+                return Ok(None);
+            }
+            self.check_rotation()?;
+        }
+        self.wait.activity();
+        match self.reader.next_with_record()? {
+            Some((packet, record)) => {
+                self.last_record = Some(record);
+                Ok(Some(packet))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The options of the file that is currently being read. They may change when the file is
+    /// rotated.
+    pub fn file_options(&self) -> FileOptions {
+        self.file_options
+    }
+
+    /// How often the file was truncated or rotated, and the reader started over.
+    pub fn reopened(&self) -> u64 {
+        self.reopened
+    }
+
+    /// Checks whether the file was truncated, rewritten or replaced, and reopens it in that case.
+    /// Returns `true` if it was reopened.
+    fn check_rotation(&mut self) -> Result<bool, PcapError> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // the old file was moved away, but the new one wasn't created yet
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let change = (metadata.len(), metadata.modified().ok());
+        let changed = change != self.last_change || change.1.is_none();
+        self.last_change = change;
+
+        let replaced = file_id(&metadata) != self.file_id;
+        let truncated = !replaced && (metadata.len() < self.reader.position() || (changed && self.rewritten()?));
+        if !replaced && !truncated {
+            return Ok(false);
+        }
+        match try_open(&self.path, self.options)? {
+            Some((file_options, reader, file_id)) => {
+                self.file_options = file_options;
+                self.reader = reader;
+                self.file_id = file_id;
+                self.reopened += 1;
+                self.last_record = None;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Checks whether the file header or the record header of the last packet that was read
+    /// differ from what is in the file now. This reads through the open file, and then moves back
+    /// to the current position.
+    fn rewritten(&mut self) -> Result<bool, PcapError> {
+        if self.reader.codec().is_some() {
+            // compressed files can't be accessed directly, only truncation is noticed
+            return Ok(false);
+        }
+        let position = self.reader.position();
+        let (raw, file_header) = self.reader.raw_parts()?;
+        let same = same_headers(raw, file_header, self.options, self.file_options, self.last_record);
+        raw.seek(SeekFrom::Start(position))?;
+        Ok(!same?)
+    }
+}
+
+/// Checks whether `file` still starts with a file header that results in `file_options`, and
+/// still contains the record `last` with the same record header.
+fn same_headers(file: &mut BufReader<File>, file_header: &def::PcapFileHeader, options: ReaderOptions, file_options: FileOptions, last: Option<RecordInfo>) -> Result<bool, PcapError> {
+    file.seek(SeekFrom::Start(0))?;
+    let (mut opts, mut fh) = match read_file_header(file) {
+        Ok(header) => header,
+        Err(PcapError::TruncatedFileHeader { .. }) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if options.apply_to_file_header(&mut opts, &mut fh).is_err() || opts != file_options {
+        return Ok(false);
+    }
+    let last = match last {
+        Some(last) => last,
+        None => return Ok(true),
+    };
+    file.seek(SeekFrom::Start(last.offset))?;
+    match read_record_header(file, file_header) {
+        Ok((rh, _)) => Ok((rh.ts_sec, rh.ts_usec, rh.incl_len, rh.orig_len) == (last.ts_sec, last.ts_usec, last.incl_len, last.orig_len)),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Opens the capture file at `path`. Returns `Ok(None)` if it doesn't exist, or its file header
+/// is not complete yet.
+#[allow(clippy::type_complexity)]
+fn try_open(path: &Path, options: ReaderOptions) -> Result<Option<(FileOptions, PcapReader<BufReader<File>>, Option<(u64, u64)>)>, PcapError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let file_id = file_id(&file.metadata()?);
+    match PcapReader::with_options(BufReader::new(file), options) {
+        Ok((file_options, reader)) => Ok(Some((file_options, reader, file_id))),
+        Err(PcapError::TruncatedFileHeader { .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process;
+
+    use super::*;
+
+    fn packet(data: u8) -> Vec<u8> {
+        vec![data, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, data, data, data, data]
+    }
+
+    #[test]
+    /// Makes sure appended packets are picked up, and truncated, rewritten and replaced files are
+    /// reopened.
+    fn follow_file() {
+        let header = [0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
+                      0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x65, 0x00, 0x00, 0x00];
+        let path = std::env::temp_dir().join(format!("rpcap-follow-{}.pcap", process::id()));
+        let rotated = path.with_extension("tmp");
+        fs::write(&path, [&header[..], &packet(1)].concat()).unwrap();
+
+        let wait = Polling::new(Duration::from_millis(1)).idle_timeout(Duration::from_millis(50));
+        let (_, mut reader) = FollowReader::with_wait(&path, ReaderOptions::default(), wait).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().data[0], 1);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        io::Write::write_all(&mut file, &packet(2)[..10]).unwrap();
+        io::Write::write_all(&mut file, &packet(2)[10..]).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().data[0], 2);
+        assert_eq!(reader.reopened(), 0);
+
+        fs::write(&path, [&header[..], &packet(3)].concat()).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().data[0], 3);
+        assert_eq!(reader.reopened(), 1);
+
+        // rewritten past the old position
+        fs::write(&path, [&header[..], &packet(5), &packet(6)].concat()).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().data[0], 5);
+        assert_eq!(reader.next().unwrap().unwrap().data[0], 6);
+        assert_eq!(reader.reopened(), 2);
+
+        if cfg!(unix) {
+            fs::write(&rotated, [&header[..], &packet(4)].concat()).unwrap();
+            fs::rename(&rotated, &path).unwrap();
+            assert_eq!(reader.next().unwrap().unwrap().data[0], 4);
+            assert_eq!(reader.reopened(), 3);
+        }
+
+        assert!(reader.next().unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,468 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::read::{read_record_header, PcapReader, ReaderOptions};
+use super::CapturedPacket;
+use super::FileOptions;
+use super::PcapError;
+use super::Timestamp;
+
+
+/// The magic number at the start of index files.
+const INDEX_MAGIC: &[u8; 8] = b"RPCAPIDX";
+/// The version of the index file format.
+const INDEX_VERSION: u32 = 1;
+/// Packet data up to this size is skipped by reading it instead of seeking, so that buffered
+/// readers don't have to discard their buffer for every packet.
+const SEEK_THRESHOLD: u64 = 4096;
+
+/// The position and time of a single packet in a `PacketIndex`.
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub struct IndexEntry {
+    /// The byte offset of the record header of the packet in the packet capture.
+    pub offset: u64,
+    /// The time when the packet was captured.
+    pub time: Timestamp,
+}
+
+/// An index of the byte offsets and timestamps of all packets in a packet capture in the
+/// traditional format.
+///
+/// An index can be saved with `write_to` and loaded with `read_from`, so that large captures only
+/// need to be scanned once. `IndexedPcapReader::open` does this automatically with a sidecar file
+/// next to the capture.
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct PacketIndex {
+    entries: Vec<IndexEntry>,
+    end: u64,
+}
+
+impl PacketIndex {
+    /// Scans the remaining records of `reader` and builds an index of them. The packet data is
+    /// skipped, not read.
+    ///
+    /// Indexing stops at the end of the file, or at a record that is cut off by the end of the
+    /// file. Afterwards, the reader is positioned after the last complete record.
+    pub fn build<R: Read + Seek>(reader: &mut PcapReader<R>) -> Result<Self, PcapError> {
+        let (reader, file_header) = reader.raw_parts()?;
+        let header_len = file_header.record_header_len();
+
+        let start = reader.stream_position()?;
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        let mut entries = Vec::new();
+        let mut offset = start;
+        while offset + header_len <= file_len {
+            let (rh, _) = read_record_header(reader, file_header)?;
+            let next = offset + header_len + u64::from(rh.incl_len);
+            if next > file_len {
+                break;
+            }
+            let time = rh.get_timestamp(file_header).ok_or(PcapError::InvalidDate)?;
+            entries.push(IndexEntry { offset, time });
+
+            let len = u64::from(rh.incl_len);
+            if len <= SEEK_THRESHOLD {
+                io::copy(&mut reader.by_ref().take(len), &mut io::sink())?;
+            } else {
+                reader.seek(SeekFrom::Start(next))?;
+            }
+            offset = next;
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+
+        Ok(PacketIndex { entries, end: offset })
+    }
+
+    /// The number of packets in the index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the index contains no packets.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The indexed packets, in the order in which they are stored in the file.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// The byte offset after the last indexed packet.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Returns the number of the first packet that was captured at or after `time`.
+    ///
+    /// This is a binary search, so it assumes the packets are stored in chronological order. For
+    /// captures where this is not the case, some packet close to `time` is returned.
+    pub fn find_time(&self, time: Timestamp) -> usize {
+        self.entries.partition_point(|entry| entry.time < time)
+    }
+
+    /// Writes the index in a compact binary format, to be loaded again with `read_from`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_all(&INDEX_VERSION.to_le_bytes())?;
+        writer.write_all(&self.end.to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for entry in &self.entries {
+            writer.write_all(&entry.offset.to_le_bytes())?;
+            writer.write_all(&entry.time.secs().to_le_bytes())?;
+            writer.write_all(&entry.time.subsec_nanos().to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the index to the file at `path`, replacing it.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Loads an index that was written with `write_to`.
+    ///
+    /// Fails with `io::ErrorKind::InvalidData` if the data is not a valid index. Whether the
+    /// index belongs to a capture is only checked by `IndexedPcapReader::with_index`.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+            let mut buf = [0; N];
+            reader.read_exact(&mut buf)?;
+            Ok(buf)
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid packet index");
+
+        if &read_array::<_, 8>(&mut reader)? != INDEX_MAGIC
+            || u32::from_le_bytes(read_array(&mut reader)?) != INDEX_VERSION {
+            return Err(invalid());
+        }
+        let end = u64::from_le_bytes(read_array(&mut reader)?);
+        let count = u64::from_le_bytes(read_array(&mut reader)?);
+
+        // don't trust the count for the allocation, the entries are read one by one anyway
+        let mut entries = Vec::with_capacity(usize::try_from(count).unwrap_or(0).min(1 << 16));
+        for _ in 0..count {
+            let offset = u64::from_le_bytes(read_array(&mut reader)?);
+            let secs = i64::from_le_bytes(read_array(&mut reader)?);
+            let nanos = u32::from_le_bytes(read_array(&mut reader)?);
+            let time = Timestamp::new(secs, nanos).ok_or_else(invalid)?;
+            if offset >= end || entries.last().is_some_and(|last: &IndexEntry| last.offset >= offset) {
+                return Err(invalid());
+            }
+            entries.push(IndexEntry { offset, time });
+        }
+        Ok(PacketIndex { entries, end })
+    }
+}
+
+/// Returns the path of the sidecar file that `IndexedPcapReader::open` uses for the index of the
+/// capture at `path`, which is the same path with `.idx` appended.
+pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut sidecar = path.as_ref().as_os_str().to_owned();
+    sidecar.push(".idx");
+    PathBuf::from(sidecar)
+}
+
+
+/// The `IndexedPcapReader` struct allows random access to the packets of a packet capture in the
+/// traditional format, using a `PacketIndex`.
+///
+/// ```no_run
+/// use rpcap::index::IndexedPcapReader;
+/// use rpcap::Timestamp;
+///
+/// let (_, mut reader) = IndexedPcapReader::open("large.pcap").unwrap();
+/// println!("{} packets", reader.packet_count());
+/// reader.seek_to_time(Timestamp::new(1_500_000_000, 0).unwrap()).unwrap();
+/// while let Some(packet) = reader.next().unwrap() {
+///     println!("{:?}", packet.time);
+/// }
+/// ```
+pub struct IndexedPcapReader<R: Read> {
+    reader: PcapReader<R>,
+    index: PacketIndex,
+}
+
+impl<R: Read + Seek> IndexedPcapReader<R> {
+    /// Create a new `IndexedPcapReader` that reads the packet capture data from the specified
+    /// reader, and builds the index by scanning the whole file.
+    pub fn new(reader: R) -> Result<(FileOptions, Self), PcapError> {
+        IndexedPcapReader::with_options(reader, ReaderOptions::default())
+    }
+
+    /// Create a new `IndexedPcapReader` like `new`, but with the specified limits and validation
+    /// options.
+    pub fn with_options(reader: R, options: ReaderOptions) -> Result<(FileOptions, Self), PcapError> {
+        let (opts, mut reader) = PcapReader::with_options(reader, options)?;
+        let index = PacketIndex::build(&mut reader)?;
+        let mut indexed = IndexedPcapReader { reader, index };
+        indexed.seek_to_packet(0)?;
+        Ok((opts, indexed))
+    }
+
+    /// Create a new `IndexedPcapReader` that uses an existing index, e.g. one that was loaded with
+    /// `PacketIndex::read_from`.
+    ///
+    /// The index is checked against the file header and the last indexed record. If it doesn't
+    /// match the packet capture, this fails with `io::ErrorKind::InvalidData`. If packets were
+    /// appended to the capture after the index was built, they are added to the index.
+    pub fn with_index(reader: R, options: ReaderOptions, mut index: PacketIndex) -> Result<(FileOptions, Self), PcapError> {
+        let (opts, mut reader) = PcapReader::with_options(reader, options)?;
+        let (raw, file_header) = reader.raw_parts()?;
+        let start = raw.stream_position()?;
+        let file_len = raw.seek(SeekFrom::End(0))?;
+
+        let mismatch = || PcapError::from(io::Error::new(io::ErrorKind::InvalidData, "packet index does not match the capture"));
+        let matches = match index.entries.last() {
+            None => index.end == start,
+            Some(last) if index.entries[0].offset == start && index.end <= file_len && last.offset < file_len => {
+                raw.seek(SeekFrom::Start(last.offset))?;
+                let (rh, _) = read_record_header(raw, file_header).map_err(|_| mismatch())?;
+                last.offset + file_header.record_header_len() + u64::from(rh.incl_len) == index.end
+                    && rh.get_timestamp(file_header) == Some(last.time)
+            },
+            Some(_) => false,
+        };
+        if !matches {
+            return Err(mismatch());
+        }
+
+        // index the packets that were appended since
+        raw.seek(SeekFrom::Start(index.end))?;
+        let appended = PacketIndex::build(&mut reader)?;
+        index.entries.extend(appended.entries);
+        index.end = appended.end;
+
+        let mut indexed = IndexedPcapReader { reader, index };
+        indexed.seek_to_packet(0)?;
+        Ok((opts, indexed))
+    }
+
+    /// The number of packets in the packet capture.
+    pub fn packet_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// The index of the packet capture.
+    pub fn index(&self) -> &PacketIndex {
+        &self.index
+    }
+
+    /// Moves to the packet number `n`, counting from `0`, so that it is returned by the next call
+    /// to `next`. Seeking to `packet_count()` moves to the end of the indexed packets.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if `n` is larger than `packet_count()`.
+    pub fn seek_to_packet(&mut self, n: usize) -> Result<(), PcapError> {
+        let offset = match self.index.entries.get(n) {
+            Some(entry) => entry.offset,
+            None if n == self.index.len() => self.index.end,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "packet number out of range").into()),
+        };
+        self.reader.raw_parts()?.0.seek(SeekFrom::Start(offset))?;
+        self.reader.set_position(offset, n as u64);
+        Ok(())
+    }
+
+    /// Moves to the first packet that was captured at or after `time`, see
+    /// `PacketIndex::find_time`. Returns the number of that packet, which is `packet_count()` if
+    /// all packets were captured before `time`.
+    pub fn seek_to_time(&mut self, time: Timestamp) -> Result<usize, PcapError> {
+        let n = self.index.find_time(time);
+        self.seek_to_packet(n)?;
+        Ok(n)
+    }
+
+    /// Reads the next packet, like `PcapReader::next`.
+    ///
+    /// Returns `Ok(None)` on EOF, or a packet as long as one is available.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<CapturedPacket<'_>>, PcapError> {
+        self.reader.next()
+    }
+
+    /// Destroys this `IndexedPcapReader` and returns the index and the underlying reader.
+    pub fn into_parts(self) -> (PacketIndex, R) {
+        (self.index, self.reader.take_reader())
+    }
+}
+
+impl IndexedPcapReader<BufReader<File>> {
+    /// Opens the packet capture at `path` with an index that is stored in a sidecar file (see
+    /// `sidecar_path`).
+    ///
+    /// If the sidecar file is missing or doesn't match the capture, the index is built and saved
+    /// to the sidecar file. If packets were appended to the capture, the saved index is extended.
+    /// Failing to save it is not an error, so read-only directories can still be used.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<(FileOptions, Self), PcapError> {
+        let path = path.as_ref();
+        let sidecar = sidecar_path(path);
+
+        let index = File::open(&sidecar).and_then(|file| PacketIndex::read_from(BufReader::new(file)));
+        if let Ok(index) = index {
+            let saved_end = index.end;
+            let file = BufReader::new(File::open(path)?);
+            match IndexedPcapReader::with_index(file, ReaderOptions::default(), index) {
+                Err(PcapError::Io(ref e)) if e.kind() == io::ErrorKind::InvalidData => {},
+                Ok((opts, reader)) => {
+                    if reader.index.end != saved_end {
+                        let _ = reader.index.save(&sidecar);
+                    }
+                    return Ok((opts, reader));
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        let (opts, reader) = IndexedPcapReader::new(BufReader::new(File::open(path)?))?;
+        let _ = reader.index.save(&sidecar);
+        Ok((opts, reader))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use super::super::write::{PcapWriter, WriteOptions};
+    use super::super::test::write_options;
+    use super::super::PcapVariant;
+
+    /// Writes a capture with 100 packets of different sizes, one every 10 seconds.
+    fn make_capture(variant: PcapVariant) -> Vec<u8> {
+        let opts = WriteOptions { variant, ..write_options() };
+        let mut writer = PcapWriter::new(Vec::new(), opts).unwrap();
+        for i in 0..100u32 {
+            let data = vec![i as u8; (i * 97 % 5000) as usize];
+            let time = Timestamp::new(1_000_000 + i64::from(i) * 10, 0).unwrap();
+            writer.write(&CapturedPacket {
+                time: time.to_time().unwrap(),
+                data: &data,
+                orig_len: data.len(),
+                extended: None,
+            }).unwrap();
+        }
+        writer.take_writer()
+    }
+
+    #[test]
+    fn random_access() {
+        for &variant in &[PcapVariant::Standard, PcapVariant::Modified] {
+            let capture = make_capture(variant);
+            let (_, mut reader) = IndexedPcapReader::new(Cursor::new(&capture)).unwrap();
+            assert_eq!(reader.packet_count(), 100);
+            assert_eq!(reader.index().end(), capture.len() as u64);
+
+            assert_eq!(reader.next().unwrap().unwrap().data.len(), 0);
+            reader.seek_to_packet(42).unwrap();
+            assert_eq!(reader.next().unwrap().unwrap().data, &[42; 42 * 97][..]);
+            reader.seek_to_packet(100).unwrap();
+            assert!(reader.next().unwrap().is_none());
+            reader.seek_to_packet(99).unwrap();
+            assert_eq!(reader.next().unwrap().unwrap().data[0], 99);
+            assert!(reader.seek_to_packet(101).is_err());
+
+            assert_eq!(reader.seek_to_time(Timestamp::new(1_000_015, 0).unwrap()).unwrap(), 2);
+            assert_eq!(reader.next().unwrap().unwrap().data[0], 2);
+            assert_eq!(reader.seek_to_time(Timestamp::new(0, 0).unwrap()).unwrap(), 0);
+            assert_eq!(reader.seek_to_time(Timestamp::new(2_000_000, 0).unwrap()).unwrap(), 100);
+        }
+    }
+
+    #[test]
+    /// Makes sure errors after a seek report the number of the packet that was sought to.
+    fn packet_numbers() {
+        let capture = make_capture(PcapVariant::Standard);
+        let options = ReaderOptions::default().max_packet_size(4000);
+        let (_, mut reader) = IndexedPcapReader::with_options(Cursor::new(&capture), options).unwrap();
+        let offset = reader.index().entries()[42].offset;
+        reader.seek_to_packet(42).unwrap();
+        assert!(matches!(reader.next(), Err(PcapError::InvalidRecordSize { packet: 42, offset: o, .. }) if o == offset));
+        reader.seek_to_packet(41).unwrap();
+        reader.next().unwrap().unwrap();
+        assert!(matches!(reader.next(), Err(PcapError::InvalidRecordSize { packet: 42, .. })));
+    }
+
+    #[test]
+    fn truncated_capture() {
+        let mut capture = make_capture(PcapVariant::Standard);
+        capture.truncate(capture.len() - 1);
+        let (_, reader) = IndexedPcapReader::new(Cursor::new(&capture)).unwrap();
+        assert_eq!(reader.packet_count(), 99);
+    }
+
+    #[test]
+    fn saved_index() {
+        let capture = make_capture(PcapVariant::Standard);
+        let (_, reader) = IndexedPcapReader::new(Cursor::new(&capture)).unwrap();
+        let (index, _) = reader.into_parts();
+
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).unwrap();
+        assert_eq!(saved.len(), 28 + 100 * 20);
+        let loaded = PacketIndex::read_from(&saved[..]).unwrap();
+        assert_eq!(loaded, index);
+        assert!(PacketIndex::read_from(&saved[..saved.len() - 1]).is_err());
+
+        let (_, mut reader) = IndexedPcapReader::with_index(Cursor::new(&capture), ReaderOptions::default(), loaded.clone()).unwrap();
+        reader.seek_to_packet(7).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().data[0], 7);
+
+        // the index of a different capture is rejected
+        let other = make_capture(PcapVariant::Modified);
+        let result = IndexedPcapReader::with_index(Cursor::new(&other), ReaderOptions::default(), loaded);
+        assert!(matches!(result, Err(PcapError::Io(ref e)) if e.kind() == io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    /// Makes sure a saved index is extended when packets are appended to the capture.
+    fn appended_packets() {
+        let capture = make_capture(PcapVariant::Standard);
+        let (_, reader) = IndexedPcapReader::new(Cursor::new(&capture)).unwrap();
+        let (index, _) = reader.into_parts();
+        let half = index.entries()[50].offset as usize;
+
+        let path = std::env::temp_dir().join(format!("rpcap-index-{}.pcap", std::process::id()));
+        let sidecar = sidecar_path(&path);
+        std::fs::write(&path, &capture[..half]).unwrap();
+        let (_, reader) = IndexedPcapReader::open(&path).unwrap();
+        assert_eq!(reader.packet_count(), 50);
+        assert!(sidecar.exists());
+
+        std::fs::write(&path, &capture).unwrap();
+        let (_, mut reader) = IndexedPcapReader::open(&path).unwrap();
+        assert_eq!(reader.index(), &index);
+        reader.seek_to_packet(99).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().data[0], 99);
+        let saved = PacketIndex::read_from(File::open(&sidecar).unwrap()).unwrap();
+        assert_eq!(saved, index);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&sidecar).unwrap();
+    }
+
+    #[test]
+    /// Makes sure a saved index is rebuilt when the capture was truncated after it was saved.
+    fn stale_index() {
+        let capture = make_capture(PcapVariant::Standard);
+        let path = std::env::temp_dir().join(format!("rpcap-stale-index-{}.pcap", std::process::id()));
+        let sidecar = sidecar_path(&path);
+        std::fs::write(&path, &capture).unwrap();
+        let (_, reader) = IndexedPcapReader::open(&path).unwrap();
+        let half = reader.index().entries()[50].offset as usize;
+        drop(reader);
+
+        std::fs::write(&path, &capture[..half]).unwrap();
+        let (_, reader) = IndexedPcapReader::open(&path).unwrap();
+        assert_eq!(reader.packet_count(), 50);
+        assert_eq!(PacketIndex::read_from(File::open(&sidecar).unwrap()).unwrap().len(), 50);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&sidecar).unwrap();
+    }
+}
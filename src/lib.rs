@@ -1,5 +1,5 @@
 //! This crate has functionality for reading and writing packet capture packet files in the
-//! traditional libpcap file format. There is no support for the newer pcapng file format.
+//! traditional libpcap file format, as well as in the newer pcapng file format.
 //!
 //! Please note that there is no functionality for capturing live packets from a network interface,
 //! this library only handles reading/writing data in the pcap file format.
@@ -8,6 +8,20 @@
 extern crate bytepack;
 #[cfg(feature = "time")]
 extern crate time;
+#[cfg(feature = "time03")]
+extern crate time03;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(feature = "xz")]
+extern crate xz2;
+#[cfg(feature = "lz4")]
+extern crate lz4_flex;
+#[cfg(all(feature = "inotify", target_os = "linux"))]
+extern crate inotify;
 
 
 mod def;
@@ -15,11 +29,26 @@ mod def;
 pub mod read;
 /// Functionality for writing packet captures.
 pub mod write;
+/// Transparent compression and decompression of packet captures.
+pub mod compress;
+/// Random access to the packets of a packet capture through an index.
+pub mod index;
+/// Reading packet captures that are still being written.
+pub mod follow;
+/// Writing packet captures that are split into several files.
+pub mod rotate;
+/// Crash-safe writing of packet capture files.
+pub mod atomic;
+mod timestamp;
+/// Asynchronous reading and writing of packet captures.
+#[cfg(any(feature = "tokio", feature = "futures"))]
+pub mod asynchronous;
 
 #[cfg(fuzzing)]
 pub mod fuzz;
 
-pub use def::Linktype;
+pub use def::{FcsLength, Linktype, PcapMagic, PcapVariant, PCAPNG_MAGIC};
+pub use timestamp::{ConvertedTimestamp, Resolution, Rounding, Timestamp};
 
 #[cfg(not(feature = "time"))]
 use std::time::SystemTime as Time;
@@ -28,6 +57,7 @@ use time::Timespec as Time;
 
 
 
+use std::convert::TryFrom;
 use std::error;
 use std::fmt;
 use std::io;
@@ -46,6 +76,116 @@ pub struct CapturedPacket<'a> {
     /// The size of the packet as it was on the wire. Might be larger than the size of `data`, in
     /// which case `data` was truncated and is incomplete.
     pub orig_len: usize,
+    /// The additional fields of the record, if the capture uses the modified format.
+    pub extended: Option<ExtendedRecord>,
+}
+
+/// The additional fields of every record in captures of the modified format (see
+/// [`PcapVariant::Modified`](enum.PcapVariant.html#variant.Modified)).
+#[derive(Copy,Clone,Eq,PartialEq,Debug,Default)]
+pub struct ExtendedRecord {
+    /// The index of the interface the packet was captured on.
+    pub ifindex: i32,
+    /// The protocol of the packet, e.g. the EtherType for Ethernet.
+    pub protocol: u16,
+    /// The type of the packet, like `sll_pkttype` in Linux: `0` for packets to this host, `4`
+    /// for outgoing packets, etc.
+    pub pkt_type: u8,
+}
+
+impl<'a> CapturedPacket<'a> {
+    /// The time when the packet was captured, as a `Timestamp`. Returns `None` if it is out of
+    /// range.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        Timestamp::from_time(self.time)
+    }
+
+    /// The time when the packet was captured, converted to any type that can be created from a
+    /// `Timestamp`, e.g. `SystemTime`, `time::OffsetDateTime` (with the `time03` feature) or
+    /// `chrono::DateTime<Utc>` (with the `chrono` feature). Returns `None` if it is out of range.
+    pub fn time_as<T: TryFrom<Timestamp>>(&self) -> Option<T> {
+        T::try_from(self.timestamp()?).ok()
+    }
+}
+
+/// The `OwnedPacket` struct contains the same information as `CapturedPacket`, but owns the packet
+/// data. This allows keeping packets around while reading the next ones, or sending them to other
+/// threads.
+///
+/// Use `OwnedPacket::from` to create one from a `CapturedPacket` or a
+/// [`InterfacePacket`](read/struct.InterfacePacket.html), and `as_borrowed` to pass it to a
+/// writer.
+#[derive(Clone,Eq,PartialEq,Debug)]
+pub struct OwnedPacket {
+    /// The time when the packet was captured.
+    pub time: Time,
+    /// The contents of the packet (possibly truncated to `orig_len` bytes during capture).
+    pub data: Vec<u8>,
+    /// The size of the packet as it was on the wire.
+    pub orig_len: usize,
+    /// The index of the interface the packet was captured on, if it was read from a pcapng file.
+    pub interface_id: Option<u32>,
+    /// The link-layer flags of the packet, if they were recorded in a pcapng file.
+    pub flags: Option<u32>,
+    /// The additional fields of the record, if the capture uses the modified format.
+    pub extended: Option<ExtendedRecord>,
+}
+impl OwnedPacket {
+    /// The time when the packet was captured, as a `Timestamp`, like
+    /// `CapturedPacket::timestamp`.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        Timestamp::from_time(self.time)
+    }
+
+    /// The time when the packet was captured, converted to another type, like
+    /// `CapturedPacket::time_as`.
+    pub fn time_as<T: TryFrom<Timestamp>>(&self) -> Option<T> {
+        T::try_from(self.timestamp()?).ok()
+    }
+
+    /// Returns a `CapturedPacket` that borrows the data of this packet.
+    pub fn as_borrowed(&self) -> CapturedPacket<'_> {
+        CapturedPacket {
+            time: self.time,
+            data: &self.data,
+            orig_len: self.orig_len,
+            extended: self.extended,
+        }
+    }
+}
+impl<'a> From<&CapturedPacket<'a>> for OwnedPacket {
+    fn from(packet: &CapturedPacket<'a>) -> OwnedPacket {
+        OwnedPacket {
+            time: packet.time,
+            data: packet.data.to_vec(),
+            orig_len: packet.orig_len,
+            interface_id: None,
+            flags: None,
+            extended: packet.extended,
+        }
+    }
+}
+impl<'a> From<&read::InterfacePacket<'a>> for OwnedPacket {
+    fn from(packet: &read::InterfacePacket<'a>) -> OwnedPacket {
+        OwnedPacket {
+            interface_id: Some(packet.interface_id),
+            flags: packet.flags,
+            ..OwnedPacket::from(&packet.packet)
+        }
+    }
+}
+
+/// The last packet of a packet capture in the traditional format, whose record is cut off by the
+/// end of the file. This is common for captures of programs that were killed while writing.
+/// It is returned in `PcapError::TruncatedPacket`.
+#[derive(Clone,Eq,PartialEq,Debug)]
+pub struct TruncatedPacket {
+    /// The byte offset of the record header, counted from the start of the file header.
+    pub offset: u64,
+    /// The packet, with the part of its data that is stored in the file.
+    pub packet: OwnedPacket,
+    /// The number of bytes of packet data that are missing at the end of the file.
+    pub missing: usize,
 }
 
 /// The options for packet capture files.
@@ -55,35 +195,145 @@ pub struct FileOptions {
     ///
     /// Packets larger than this usually get truncated to this size by the recording application.
     pub snaplen: usize,
-    /// The type of packets in the file. See `Linktype` for known values.
-    pub linktype: u32,
+    /// The type of packets in the file.
+    pub linktype: Linktype,
+    /// The length of the frame check sequence at the end of every packet, if the file header
+    /// specifies it.
+    pub fcs_length: Option<FcsLength>,
+    /// The reserved bits of the link type field in the file header, in their original position.
+    /// They should be `0`, but are preserved so that files can be copied without changes. If
+    /// `fcs_length` is `None`, this includes the bits that would contain the FCS length.
+    pub linktype_reserved: u32,
     /// Determines the timestamp format of packets the file.
     pub high_res_timestamps: bool,
+    /// The variant of the file format. Only the standard variant supports both timestamp
+    /// formats.
+    pub variant: PcapVariant,
     /// Determines the byte order for the file headers.
     pub non_native_byte_order: bool,
+    /// The offset of the timestamps from UTC in seconds (`thiszone` in the file header).
+    ///
+    /// Readers add this offset to the timestamps of all packets, unless that is disabled with
+    /// [`ReaderOptions::apply_utc_offset`](read/struct.ReaderOptions.html#method.apply_utc_offset).
+    /// Writers subtract it again, so copying packets between files with the same options does not
+    /// change the stored timestamps. In practice, this is always `0`.
+    pub utc_offset: i32,
+    /// The accuracy of the timestamps (`sigfigs` in the file header). In practice, this is always
+    /// `0`.
+    pub sigfigs: u32,
+}
+
+impl FileOptions {
+    /// The resolution of timestamps in the file.
+    pub fn resolution(&self) -> Resolution {
+        if self.high_res_timestamps {
+            Resolution::NANOSECONDS
+        } else {
+            Resolution::MICROSECONDS
+        }
+    }
+}
+
+/// The options of a single capture interface in a pcapng file.
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub struct InterfaceOptions {
+    /// The maximum size of a packet captured on this interface, or `0` if there is no limit.
+    pub snaplen: usize,
+    /// The type of packets captured on this interface.
+    pub linktype: Linktype,
+    /// The resolution of timestamps. The default is microseconds.
+    pub ts_resolution: Resolution,
+    /// Offset in seconds that is added to all timestamps of packets from this interface.
+    pub ts_offset: i64,
 }
 
 
 /// The error type for this crate.
+///
+/// The more specific variants with context are returned where the context is known, the generic
+/// ones otherwise. New variants may be added in the future.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum PcapError {
     /// An error that was returned by the underlying reader/writer.
     ///
     /// Possible recovery depends on the underlying reader/writer.
     Io(io::Error),
     /// A packet could not be returned because it's size exceeds that specified in the pcap file
-    /// header, or that of the target architecture's `usize`.
+    /// header, or that of the target architecture's `usize`. The readers for the traditional
+    /// format return the more specific `InvalidRecordSize` instead.
     ///
     /// You can try recovering by reading the next packet, but it is likely the pcap file is
-    /// corrupted.
+    /// corrupted. In that case, the next packet will most likely be garbage as well; use
+    /// `read::ReaderOptions::recover` to find the next valid packet instead.
     InvalidPacketSize,
     /// A packet could not be returned because it has an invalid timestamp.
     ///
     /// You can try recovering by reading the next packet, but it is likely the pcap file is
-    /// corrupted.
+    /// corrupted, see `InvalidPacketSize`.
     InvalidDate,
     /// The pcap file header could not be parsed.
     InvalidFileHeader,
+    /// A packet could not be written because its timestamp can't be represented in the file, e.g.
+    /// because it is before 1970 or after 2106 in a file of the traditional format.
+    ///
+    /// The packet was not written. You can continue writing other packets.
+    TimestampOutOfRange,
+    /// The file does not start with a known magic number. The value is the first four bytes of the
+    /// file, in native byte order.
+    BadMagic(u32),
+    /// The version of the traditional pcap format in the file header is not supported.
+    UnsupportedVersion {
+        /// The major version number.
+        major: u16,
+        /// The minor version number.
+        minor: u16,
+    },
+    /// The snap length in the file header is larger than the limit set with
+    /// `read::ReaderOptions::max_snaplen`, or than what fits in a `usize`.
+    SnaplenTooLarge {
+        /// The snap length in the file header.
+        snaplen: u32,
+        /// The largest accepted snap length.
+        max: usize,
+    },
+    /// The file ends before its file header is complete.
+    TruncatedFileHeader {
+        /// The number of bytes that are needed: the size of the file header, or that of the magic
+        /// number if the format is not known yet.
+        expected: usize,
+        /// The number of bytes that were available.
+        got: usize,
+    },
+    /// A record in a file of the traditional format was rejected because of its size, see
+    /// `read::ReaderOptions`. The packet data was skipped.
+    ///
+    /// Recovery works like for `InvalidPacketSize`.
+    InvalidRecordSize {
+        /// The number of the packet in the file, counting from `0`.
+        packet: u64,
+        /// The byte offset of the record header, counted from the start of the file header.
+        offset: u64,
+        /// The length of the packet data that is stored in the file.
+        incl_len: u32,
+        /// The original length of the packet.
+        orig_len: u32,
+    },
+    /// The file ends in the middle of a record header, or of a block header in a pcapng file. If
+    /// only the packet data is incomplete, `TruncatedPacket` is returned instead.
+    TruncatedRecord {
+        /// The byte offset of the record or block header, counted from the start of the file.
+        offset: u64,
+        /// The size of the record header.
+        expected: usize,
+        /// The number of bytes of the record header that were available.
+        got: usize,
+    },
+    /// The file ends in the middle of the packet data of a record. The packet contains the part of
+    /// the data that was available.
+    ///
+    /// This is always the last packet of the file, the next read returns EOF.
+    TruncatedPacket(Box<TruncatedPacket>),
 }
 impl From<io::Error> for PcapError {
     fn from(err: io::Error) -> PcapError {
@@ -102,6 +352,31 @@ impl fmt::Display for PcapError {
             PcapError::InvalidPacketSize => "Parsed packet has an invalid size.",
             PcapError::InvalidDate => "Parsed packet has an invalid date.",
             PcapError::InvalidFileHeader => "The pcap file has an invalid/unknown file header.",
+            PcapError::TimestampOutOfRange => "The timestamp of the packet can't be represented in the file.",
+            PcapError::BadMagic(magic) => {
+                return write!(f, "The file starts with the unknown magic number {:#010x}.", magic);
+            },
+            PcapError::UnsupportedVersion { major, minor } => {
+                return write!(f, "The pcap file has the unsupported format version {}.{}.", major, minor);
+            },
+            PcapError::SnaplenTooLarge { snaplen, max } => {
+                return write!(f, "The snap length {} of the pcap file exceeds the limit of {}.", snaplen, max);
+            },
+            PcapError::TruncatedFileHeader { expected, got } => {
+                return write!(f, "The file header is truncated: expected {} bytes, got {}.", expected, got);
+            },
+            PcapError::InvalidRecordSize { packet, offset, incl_len, orig_len } => {
+                return write!(f, "Packet {} at offset {} has an invalid size ({} bytes stored, original length {}).",
+                              packet, offset, incl_len, orig_len);
+            },
+            PcapError::TruncatedRecord { offset, expected, got } => {
+                return write!(f, "The record header at offset {} is truncated: expected {} bytes, got {}.",
+                              offset, expected, got);
+            },
+            PcapError::TruncatedPacket(ref truncated) => {
+                return write!(f, "The packet at offset {} is truncated: {} bytes of packet data are missing.",
+                              truncated.offset, truncated.missing);
+            },
         };
         write!(f, "{}", desc)
     }
@@ -117,12 +392,12 @@ impl error::Error for PcapError {
 
 #[cfg(test)]
 mod test {
-    use std::io::{Cursor, Write};
+    use std::io::{self, Cursor, Write};
 
-    use super::write::{PcapWriter, WriteOptions};
-    use super::read::PcapReader;
+    use super::write::{PcapNgWriter, PcapWriter, WriteOptions};
+    use super::read::{PcapNgReader, PcapReader};
     use super::Time;
-    use super::{CapturedPacket, Linktype};
+    use super::{CapturedPacket, InterfaceOptions, Linktype, OwnedPacket, PcapError, PcapVariant, Resolution, Rounding, Timestamp};
 
     #[cfg(not(feature = "time"))]
     use ::std::time::{Duration, UNIX_EPOCH};
@@ -130,6 +405,22 @@ mod test {
     extern crate rand;
     use self::rand::Rng;
 
+    /// Options for a capture of raw IP packets in the standard format, for tests that don't care
+    /// about the file header. Change single fields with struct update syntax.
+    pub(crate) fn write_options() -> WriteOptions {
+        WriteOptions {
+            high_res_timestamps: false,
+            variant: PcapVariant::Standard,
+            non_native_byte_order: false,
+            snaplen: 0xffff,
+            linktype: Linktype::RAW,
+            fcs_length: None,
+            linktype_reserved: 0,
+            utc_offset: 0,
+            sigfigs: 0,
+        }
+    }
+
     /// Generates 10 buffers with random data.
     fn gen_packet_data() -> Vec<Vec<u8>> {
         let mut rng = rand::thread_rng();
@@ -151,18 +442,19 @@ mod test {
     }
 
     /// Generates a random packet for every buffer in `contents`.
-    fn gen_packets<'a>(contents: &'a Vec<Vec<u8>>, snaplen: usize) -> Vec<CapturedPacket<'a>> {
+    fn gen_packets<'a>(contents: &'a [Vec<u8>], snaplen: usize) -> Vec<CapturedPacket<'a>> {
         let mut rng = rand::thread_rng();
 
         contents.iter()
             .map(|data| {
-                let s = rng.gen_range(0..(u64::from(u32::max_value()) + 1));
+                let s = rng.gen_range(0..(u64::from(u32::MAX) + 1));
                 let ns = rng.gen_range(0..1_000_000_000);
 
                 CapturedPacket {
                     time: make_time(s, ns),
                     data: &data[..usize::min(data.len(), snaplen)],
                     orig_len: data.len(),
+                    extended: None,
                 }
             })
             .collect()
@@ -171,7 +463,7 @@ mod test {
     /// Writes `packets` to the `writer`. Returns the underlying writer of `writer`.
     fn write_packets<W: Write>(mut writer: PcapWriter<W>, packets: &[CapturedPacket]) -> W {
         for p in &packets[..packets.len()] {
-            writer.write(&p).unwrap();
+            writer.write(p).unwrap();
         }
         writer.take_writer()
     }
@@ -185,9 +477,14 @@ mod test {
 
         let opts = WriteOptions {
             high_res_timestamps: true,
+            variant: PcapVariant::Standard,
             non_native_byte_order: false,
             snaplen: MAX_PACKET_SIZE,
-            linktype: Linktype::NULL.into(),
+            linktype: Linktype::NULL,
+            fcs_length: None,
+            linktype_reserved: 0,
+            utc_offset: 0,
+            sigfigs: 0,
         };
 
         let buf = write_packets(PcapWriter::new(Vec::new(), opts).unwrap(), &packets[..packets.len()/2]);
@@ -202,6 +499,202 @@ mod test {
         }
         assert!(reader.next().unwrap().is_none());
     }
+
+    #[test]
+    fn owned_packets() {
+        let contents = gen_packet_data();
+        let packets = gen_packets(&contents, usize::MAX);
+
+        let opts = WriteOptions {
+            high_res_timestamps: true,
+            variant: PcapVariant::Standard,
+            non_native_byte_order: false,
+            snaplen: 0xffff,
+            linktype: Linktype::RAW,
+            fcs_length: None,
+            linktype_reserved: 0,
+            utc_offset: 0,
+            sigfigs: 0,
+        };
+        let buf = write_packets(PcapWriter::new(Vec::new(), opts).unwrap(), &packets);
+
+        // buffer all packets, then write them in reverse order
+        let (_, reader) = PcapReader::new(buf.as_slice()).unwrap();
+        let owned = reader.into_owned_iter().collect::<Result<Vec<OwnedPacket>, _>>().unwrap();
+        let mut writer = PcapWriter::new(Vec::new(), opts).unwrap();
+        for p in owned.iter().rev() {
+            assert_eq!(p.interface_id, None);
+            writer.write(&p.as_borrowed()).unwrap();
+        }
+        let buf = writer.take_writer();
+
+        let (_, mut reader) = PcapReader::new(buf.as_slice()).unwrap();
+        for expect in packets.iter().rev() {
+            let actual = reader.next().unwrap().unwrap();
+            assert_eq!(actual, *expect);
+            assert_eq!(OwnedPacket::from(&actual).as_borrowed(), *expect);
+        }
+        assert!(reader.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_write_pcapng() {
+        let contents = gen_packet_data();
+        let packets = gen_packets(&contents, usize::MAX);
+
+        let interfaces = [
+            InterfaceOptions {
+                snaplen: 0,
+                linktype: Linktype::ETHERNET,
+                ts_resolution: Resolution::NANOSECONDS,
+                ts_offset: 0,
+            },
+            InterfaceOptions {
+                snaplen: 500,
+                linktype: Linktype::RAW,
+                ts_resolution: Resolution::Binary(30),
+                ts_offset: -1000,
+            },
+        ];
+
+        for non_native_byte_order in [false, true] {
+            let mut writer = PcapNgWriter::new(Vec::new(), non_native_byte_order).unwrap();
+            for iface in interfaces {
+                writer.add_interface(iface).unwrap();
+            }
+            for (i, p) in packets.iter().enumerate() {
+                writer.write((i % 2) as u32, p).unwrap();
+            }
+            let buf = writer.take_writer();
+
+            let mut reader = PcapNgReader::new(buf.as_slice()).unwrap();
+            for (i, expect) in packets.iter().enumerate() {
+                let actual = reader.next().unwrap().unwrap();
+                assert_eq!(actual.interface_id as usize, i % 2);
+                assert_eq!(actual.interface, interfaces[i % 2]);
+                assert_eq!(actual.packet.orig_len, expect.orig_len);
+                if i % 2 == 0 {
+                    assert_eq!(actual.packet, *expect);
+                } else {
+                    assert_eq!(actual.packet.data, &expect.data[..usize::min(expect.data.len(), 500)]);
+                }
+            }
+            assert!(reader.next().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    /// Makes sure invalid interfaces and interface ids are rejected as invalid input.
+    fn pcapng_invalid_input() {
+        let iface = InterfaceOptions {
+            snaplen: 0,
+            linktype: Linktype::RAW,
+            ts_resolution: Resolution::Decimal(20),
+            ts_offset: 0,
+        };
+        let packet = CapturedPacket { time: make_time(10, 0), data: &[], orig_len: 0, extended: None };
+        let mut writer = PcapNgWriter::new(Vec::new(), false).unwrap();
+        assert!(matches!(writer.add_interface(iface), Err(PcapError::Io(ref e)) if e.kind() == io::ErrorKind::InvalidInput));
+        assert!(matches!(writer.write(0, &packet), Err(PcapError::Io(ref e)) if e.kind() == io::ErrorKind::InvalidInput));
+        assert!(writer.interfaces().is_empty());
+    }
+
+    #[test]
+    /// Makes sure timestamps before 1970 and after 2106 can be written to pcapng files, and are
+    /// rejected for files of the traditional format.
+    fn timestamp_range() {
+        let times = [Timestamp::new(-1_000_000, 5).unwrap(), Timestamp::new(1 << 33, 999_999_999).unwrap()];
+        let packets: Vec<_> = times.iter()
+            .map(|ts| CapturedPacket {
+                time: ts.to_time().unwrap(),
+                data: &[1, 2, 3],
+                orig_len: 3,
+                extended: None,
+            })
+            .collect();
+
+        let iface = InterfaceOptions {
+            snaplen: 0,
+            linktype: Linktype::RAW,
+            ts_resolution: Resolution::NANOSECONDS,
+            ts_offset: -2_000_000,
+        };
+        let mut writer = PcapNgWriter::new(Vec::new(), false).unwrap();
+        writer.add_interface(iface).unwrap();
+        for p in &packets {
+            writer.write(0, p).unwrap();
+        }
+        let buf = writer.take_writer();
+        let mut reader = PcapNgReader::new(buf.as_slice()).unwrap();
+        for p in &packets {
+            assert_eq!(reader.next().unwrap().unwrap().packet, *p);
+        }
+
+        let opts = WriteOptions {
+            high_res_timestamps: false,
+            variant: PcapVariant::Standard,
+            non_native_byte_order: false,
+            snaplen: 100,
+            linktype: Linktype::RAW,
+            fcs_length: None,
+            linktype_reserved: 0,
+            utc_offset: 0,
+            sigfigs: 0,
+        };
+        let mut writer = PcapWriter::new(Vec::new(), opts).unwrap();
+        for p in &packets {
+            assert!(matches!(writer.write(p), Err(PcapError::TimestampOutOfRange)));
+        }
+        assert_eq!(writer.take_writer().len(), 24);
+    }
+
+    #[test]
+    fn timestamp_rounding() {
+        let exact = CapturedPacket { time: make_time(10, 5_000), data: &[], orig_len: 0, extended: None };
+        let lossy = CapturedPacket { time: make_time(10, 999_999_600), data: &[], orig_len: 0, extended: None };
+        let opts = WriteOptions {
+            high_res_timestamps: false,
+            variant: PcapVariant::Standard,
+            non_native_byte_order: false,
+            snaplen: 100,
+            linktype: Linktype::RAW,
+            fcs_length: None,
+            linktype_reserved: 0,
+            utc_offset: 0,
+            sigfigs: 0,
+        };
+
+        for &(rounding, expected) in &[(Rounding::Round, make_time(11, 0)),
+                                       (Rounding::Truncate, make_time(10, 999_999_000)),
+                                       (Rounding::Ceil, make_time(11, 0))] {
+            let mut writer = PcapWriter::new(Vec::new(), opts).unwrap();
+            writer.set_rounding(rounding);
+            writer.write(&exact).unwrap();
+            writer.write(&lossy).unwrap();
+            assert_eq!(writer.lossy_timestamps(), 1);
+
+            let buf = writer.take_writer();
+            let (_, mut reader) = PcapReader::new(buf.as_slice()).unwrap();
+            assert_eq!(reader.next().unwrap().unwrap().time, exact.time);
+            assert_eq!(reader.next().unwrap().unwrap().time, expected);
+        }
+
+        let iface = InterfaceOptions {
+            snaplen: 0,
+            linktype: Linktype::RAW,
+            ts_resolution: Resolution::Binary(10),
+            ts_offset: 0,
+        };
+        let mut writer = PcapNgWriter::new(Vec::new(), false).unwrap();
+        writer.add_interface(iface).unwrap();
+        writer.set_rounding(Rounding::Truncate);
+        writer.write(0, &lossy).unwrap();
+        assert_eq!(writer.lossy_timestamps(), 1);
+        let buf = writer.take_writer();
+        let mut reader = PcapNgReader::new(buf.as_slice()).unwrap();
+        // 1023/1024 of a second
+        assert_eq!(reader.next().unwrap().unwrap().packet.time, make_time(10, 999_023_437));
+    }
 }
 
 
@@ -1,13 +1,23 @@
 
+use std::collections::VecDeque;
 use std::io;
 use std::io::Read;
+use std::mem;
 use std::convert::TryFrom;
+use std::ops::Range;
+use std::time::Duration;
 
+use super::compress::{Codec, Decompress};
 use super::def;
 use super::CapturedPacket;
+use super::ExtendedRecord;
 use super::FileOptions;
+use super::InterfaceOptions;
+use super::OwnedPacket;
 use super::PcapError;
+use super::TruncatedPacket;
 
+use bytepack::Packed;
 use bytepack::Unpacker as NativeUnpacker;
 
 #[cfg(target_endian = "big")]
@@ -15,101 +25,1146 @@ use bytepack::LEUnpacker as NonNativeUnpacker;
 #[cfg(target_endian = "little")]
 use bytepack::BEUnpacker as NonNativeUnpacker;
 
+/// The default limit for the size of a single packet, see `ReaderOptions::max_packet_size`.
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 0x6000_0000;
+/// The packet buffer grows by at least this much at once, but never by more than the amount of
+/// data that was already read for a packet (see `grow_buffer`).
+const BUFFER_CHUNK: usize = 0x10000;
+
+/// In recovery mode, how many seconds a record may be older than the previous one and still be
+/// considered plausible by default, see `ReaderOptions::recover_max_backwards`.
+const RECOVER_MAX_BACKWARDS: u32 = 60;
+/// In recovery mode, how many seconds a record that was found by resynchronisation may be newer
+/// than the previous one.
+const RECOVER_MAX_FORWARDS: u32 = 24 * 60 * 60;
+
+/// What to do with packets that are larger than the snap length in the file header.
+#[derive(Copy,Clone,PartialEq,Eq,Debug,Default)]
+pub enum OversizedPackets {
+    /// Truncate the packet data to the snap length, and silently drop the rest.
+    #[default]
+    Truncate,
+    /// Skip the packet, and return `PcapError::InvalidRecordSize`.
+    Error,
+    /// Return the full packet data.
+    Keep,
+}
+
+/// Options that control how strictly a `PcapReader` validates the packet capture, and which limits
+/// it enforces. They are set with the builder methods, starting from the lenient defaults.
+///
+/// ```
+/// use rpcap::read::{OversizedPackets, ReaderOptions};
+///
+/// let strict = ReaderOptions::new()
+///     .max_snaplen(0xffff)
+///     .oversized_packets(OversizedPackets::Error)
+///     .reject_truncated_orig_len(true);
+/// ```
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub struct ReaderOptions {
+    max_snaplen: usize,
+    max_packet_size: usize,
+    oversized_packets: OversizedPackets,
+    reject_truncated_orig_len: bool,
+    apply_utc_offset: bool,
+    recover: bool,
+    recover_max_backwards: u32,
+    follow: bool,
+    nokia: bool,
+}
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        ReaderOptions {
+            max_snaplen: usize::MAX,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            oversized_packets: OversizedPackets::Truncate,
+            reject_truncated_orig_len: false,
+            apply_utc_offset: true,
+            recover: false,
+            recover_max_backwards: RECOVER_MAX_BACKWARDS,
+            follow: false,
+            nokia: false,
+        }
+    }
+}
+impl ReaderOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the maximum snap length that is accepted in the file header. Files with a larger snap
+    /// length are rejected with `PcapError::SnaplenTooLarge`. There is no limit by default.
+    pub fn max_snaplen(mut self, max_snaplen: usize) -> Self {
+        self.max_snaplen = max_snaplen;
+        self
+    }
+    /// Sets the maximum size of a single packet that is returned. This protects against excessive
+    /// memory allocations for corrupted or malicious files. The default is
+    /// `DEFAULT_MAX_PACKET_SIZE`.
+    ///
+    /// The internal packet buffer of `PcapReader` only grows as large as the largest packet in the
+    /// file, so the limit can be much larger than the packets that are actually expected. Packets
+    /// that are larger than the limit are skipped, and `PcapError::InvalidRecordSize` is returned
+    /// for them.
+    pub fn max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+    /// Sets what happens with packets that are larger than the snap length in the file header.
+    /// They are truncated by default.
+    pub fn oversized_packets(mut self, oversized_packets: OversizedPackets) -> Self {
+        self.oversized_packets = oversized_packets;
+        self
+    }
+    /// Sets whether packets whose original length is smaller than the amount of data stored in the
+    /// file are skipped with `PcapError::InvalidRecordSize`. They are accepted by default.
+    pub fn reject_truncated_orig_len(mut self, reject: bool) -> Self {
+        self.reject_truncated_orig_len = reject;
+        self
+    }
+
+    /// Sets whether the UTC offset from the file header (see `FileOptions::utc_offset`) is added
+    /// to the timestamps of the packets. This is done by default. If it is disabled, the
+    /// timestamps are returned exactly as they are stored in the file, so the packets should only
+    /// be written to files with a `utc_offset` of `0`.
+    pub fn apply_utc_offset(mut self, apply: bool) -> Self {
+        self.apply_utc_offset = apply;
+        self
+    }
+
+    /// Sets whether captures with the standard magic number for microsecond timestamps are read as
+    /// `PcapVariant::Nokia`, which can't be detected from the file header. This is disabled by
+    /// default.
+    pub fn nokia(mut self, nokia: bool) -> Self {
+        self.nokia = nokia;
+        self
+    }
+
+    /// Sets whether `PcapReader` recovers from corrupted records. This is disabled by default.
+    ///
+    /// In recovery mode, every record header is checked for plausibility: the timestamp has to be
+    /// valid and not more than 60 seconds older than that of the previous packet (see
+    /// `recover_max_backwards`), the stored length must not exceed the snap length (or the maximum
+    /// packet size, if oversized packets are kept), and the original length must not be smaller
+    /// than the stored length. If a record header is not plausible, the reader scans forward byte by byte until it finds a plausible record header
+    /// that is followed by another plausible record header (or the end of the file), and
+    /// continues reading there. The bytes that were skipped, including a partial record header at
+    /// the end of the file, are reported by `PcapReader::skipped_ranges`.
+    ///
+    /// The other readers ignore this option.
+    pub fn recover(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    /// Sets how much older than the previous packet a packet may be in recovery mode (see
+    /// `recover`). The default is 60 seconds. Records that step further back in time are treated
+    /// as corrupted and skipped, so this has to be raised for captures that were merged or whose
+    /// clock was adjusted. Fractions of a second are ignored.
+    pub fn recover_max_backwards(mut self, max: Duration) -> Self {
+        self.recover_max_backwards = u32::try_from(max.as_secs()).unwrap_or(u32::MAX);
+        self
+    }
+
+    /// Sets whether `PcapReader` keeps reading after the end of the file, for captures that are
+    /// still being written. This is disabled by default.
+    ///
+    /// In follow mode, `next` returns `Ok(None)` whenever no complete record is available, and
+    /// can be called again once more data was written. A record that is only partially written is
+    /// kept in memory until the rest of it is available, instead of being reported as truncated.
+    /// See `follow::FollowReader` for waiting for new data in files.
+    ///
+    /// The other readers ignore this option.
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Checks the file header against the limits, and adjusts how the timestamps and records are
+    /// interpreted.
+    pub(crate) fn apply_to_file_header(&self, opts: &mut FileOptions, file_header: &mut def::PcapFileHeader) -> Result<(), PcapError> {
+        if file_header.snaplen > self.max_snaplen {
+            // the snap length was read as a u32
+            return Err(PcapError::SnaplenTooLarge { snaplen: file_header.snaplen as u32, max: self.max_snaplen });
+        }
+        if !self.apply_utc_offset {
+            file_header.utc_offset = 0;
+        }
+        if self.nokia && file_header.variant == def::PcapVariant::Standard && !file_header.ns_res {
+            file_header.variant = def::PcapVariant::Nokia;
+            opts.variant = def::PcapVariant::Nokia;
+        }
+        Ok(())
+    }
+
+    /// Checks a record header, and returns how many bytes of its packet data should be returned.
+    /// If this fails, the packet data has to be skipped. `packet` and `offset` are the number and
+    /// the position of the record, for the error.
+    pub(crate) fn packet_size(&self, rh: &def::PcapRecordHeader, file_header: &def::PcapFileHeader, packet: u64, offset: u64) -> Result<usize, PcapError> {
+        let invalid = || invalid_record_size(rh, packet, offset);
+        let size_in_pcap = usize::try_from(rh.incl_len).map_err(|_| invalid())?;
+        let size = if size_in_pcap <= file_header.snaplen {
+            size_in_pcap
+        } else {
+            match self.oversized_packets {
+                OversizedPackets::Truncate => file_header.snaplen,
+                OversizedPackets::Error => return Err(invalid()),
+                OversizedPackets::Keep => size_in_pcap,
+            }
+        };
+        if size > self.max_packet_size || (self.reject_truncated_orig_len && rh.orig_len < rh.incl_len) {
+            return Err(invalid());
+        }
+        Ok(size)
+    }
+
+    /// Checks whether a record header looks valid in recovery mode. `prev_ts_sec` is the
+    /// timestamp of the previous record, if there is one. Records that are found by
+    /// resynchronisation must also not be much newer than the previous record.
+    fn plausible_record(&self, rh: &def::PcapRecordHeader, file_header: &def::PcapFileHeader, prev_ts_sec: Option<u32>, resync: bool) -> bool {
+        let max_subsec = if file_header.ns_res { 1_000_000_000 } else { 1_000_000 };
+        let max_len = if self.oversized_packets == OversizedPackets::Keep {
+            self.max_packet_size
+        } else {
+            usize::min(file_header.snaplen, self.max_packet_size)
+        };
+        let (ts_sec, ts_usec, incl_len, orig_len) = (rh.ts_sec, rh.ts_usec, rh.incl_len, rh.orig_len);
+        let plausible_time = prev_ts_sec.is_none_or(|prev| {
+            ts_sec >= prev.saturating_sub(self.recover_max_backwards)
+                && (!resync || ts_sec <= prev.saturating_add(RECOVER_MAX_FORWARDS))
+        });
+        ts_usec < max_subsec
+            && usize::try_from(incl_len).is_ok_and(|len| len <= max_len)
+            && orig_len >= incl_len
+            && plausible_time
+    }
+}
+
+/// Where a packet is stored in the packet capture, and the raw values of its record header. This is
+/// returned by `PcapReader::next_with_record`.
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub struct RecordInfo {
+    /// The byte offset of the record header, counted from the start of the file header.
+    pub offset: u64,
+    /// The seconds of the timestamp, as stored in the file.
+    pub ts_sec: u32,
+    /// The microseconds or nanoseconds of the timestamp, as stored in the file.
+    pub ts_usec: u32,
+    /// The length of the packet data that is stored in the file.
+    pub incl_len: u32,
+    /// The original length of the packet, as stored in the file.
+    pub orig_len: u32,
+    /// `true` if the packet data was cut to the snap length of the file, see
+    /// `OversizedPackets::Truncate`.
+    pub truncated: bool,
+}
+
 /// The `PcapReader` struct allows reading packets from a packet capture.
-pub struct PcapReader<R> {
-    reader: R,
-    state: Option<PcapState>,
+pub struct PcapReader<R: io::Read> {
+    reader: Decompress<R>,
+    state: PcapState,
+    eof: bool,
+    position: u64,
+    /// The number of records that were read so far.
+    packets: u64,
+    /// Data that was read ahead while resynchronising, and has to be returned before reading
+    /// more from `reader`.
+    lookahead: VecDeque<u8>,
+    prev_ts_sec: Option<u32>,
+    skipped: Vec<Range<u64>>,
+    /// The number of bytes at the end of the next record that were already skipped in follow
+    /// mode, because they are not returned.
+    discarded: u64,
 }
 struct PcapState {
     file_header: def::PcapFileHeader,
     packet_buffer: Vec<u8>,
+    options: ReaderOptions,
 }
 
 
 impl<R: io::Read> PcapReader<R> {
     /// Create a new `PcapReader` that reads the packet capture data from the specified `Reader`.
-    pub fn new(mut reader: R) -> Result<(FileOptions, Self), PcapError> {
-        let fh : def::PcapFileHeaderInFile = NativeUnpacker::unpack(&mut reader)?;
-        let fh = def::PcapFileHeader::try_from(fh).or(Err(PcapError::InvalidFileHeader))?;
+    ///
+    /// Compressed captures are decompressed transparently, if the cargo feature for their
+    /// compression format is enabled.
+    pub fn new(reader: R) -> Result<(FileOptions, Self), PcapError> {
+        PcapReader::with_options(reader, ReaderOptions::default())
+    }
 
-        // DOS protection TODO: make this limit (1.5GiB) configurable
-        if fh.snaplen > 0x60000000 {
-            return Err(PcapError::InvalidFileHeader);
-        }
-        let buffer = vec![0; fh.snaplen];
+    /// Create a new `PcapReader` like `new`, but with the specified limits and validation
+    /// options.
+    pub fn with_options(reader: R, options: ReaderOptions) -> Result<(FileOptions, Self), PcapError> {
+        let mut reader = Decompress::new(reader)?;
+        let (mut opts, mut fh) = read_file_header(&mut reader)?;
+        options.apply_to_file_header(&mut opts, &mut fh)?;
 
         Ok((
-            FileOptions {
-                snaplen: fh.snaplen,
-                linktype: fh.network,
-                high_res_timestamps: fh.ns_res,
-                non_native_byte_order: fh.need_byte_swap,
-            },
+            opts,
             PcapReader {
                 reader,
-                state: Some(PcapState {
+                state: PcapState {
                     file_header: fh,
-                    packet_buffer: buffer,
-                }),
+                    packet_buffer: Vec::new(),
+                    options,
+                },
+                eof: false,
+                position: def::PcapFileHeader::LEN,
+                packets: 0,
+                lookahead: VecDeque::new(),
+                prev_ts_sec: None,
+                skipped: Vec::new(),
+                discarded: 0,
             }
         ))
     }
+
     /// This function allows iterating over the packets in the packet capture, in a similar fashion
     /// to normal iterators. (The exact interface is unfortunately incompatible.)
     ///
     /// Returns `Ok(None)` on EOF, or a packet as long as one is available.
     #[allow(clippy::should_implement_trait)]
-    pub fn next(&mut self) -> Result<Option<CapturedPacket>, PcapError> {
+    pub fn next(&mut self) -> Result<Option<CapturedPacket<'_>>, PcapError> {
+        Ok(self.next_with_record()?.map(|(packet, _)| packet))
+    }
 
-        let rh = if let Some(PcapState { file_header: def::PcapFileHeader { need_byte_swap: true, .. }, .. }) = self.state {
-            NonNativeUnpacker::unpack::<def::PcapRecordHeader>(&mut self.reader)
+    /// Reads the next packet like `next`, but also returns where its record is stored in the file
+    /// and the raw values of the record header.
+    pub fn next_with_record(&mut self) -> Result<Option<(CapturedPacket<'_>, RecordInfo)>, PcapError> {
+        if self.eof {
+            return Ok(None);
+        }
+        if self.state.options.follow && !self.complete_record_available()? {
+            return Ok(None);
+        }
+        let header = if self.state.options.recover {
+            self.read_plausible_header()?
         } else {
-            NativeUnpacker::unpack::<def::PcapRecordHeader>(&mut self.reader)
+            self.read_header()?
         };
-        let rh = match rh {
-            Err(e) => {
-                return if e.kind() == io::ErrorKind::UnexpectedEof {
-                    self.state = None;
-                    Ok(None)
-                } else {
-                    Err(e.into())
-                };
+        let (rh, extended) = match header {
+            None => {
+                self.eof = !self.state.options.follow;
+                return Ok(None);
             },
-            Ok(rh) => rh,
+            Some(header) => header,
         };
-        let state = self.state.as_mut().unwrap();
+        let state = &mut self.state;
+        let offset = self.position;
+        let packet = self.packets;
+        self.position += state.file_header.record_header_len();
+        self.packets += 1;
+        self.prev_ts_sec = Some(rh.ts_sec);
+        let discarded = mem::take(&mut self.discarded);
+        let mut input = (&mut self.lookahead).chain(&mut self.reader);
 
-        let size_in_pcap = usize::try_from(rh.incl_len).or(Err(PcapError::InvalidPacketSize))?;
-        let size_to_read = usize::min(state.packet_buffer.len(), size_in_pcap);
+        let size_in_pcap = usize::try_from(rh.incl_len).map_err(|_| invalid_record_size(&rh, packet, offset))?;
+        let size_to_read = match state.options.packet_size(&rh, &state.file_header, packet, offset) {
+            Ok(size) => size,
+            Err(e) => {
+                let mut take = input.by_ref().take(u64::from(rh.incl_len) - discarded);
+                self.position += discarded + io::copy(&mut take, &mut io::sink())?;
+                return Err(e);
+            },
+        };
 
-        let buf = &mut state.packet_buffer[..size_to_read];
-        self.reader.read_exact(buf)?;
+        let mut got = read_into_buffer(&mut input, &mut state.packet_buffer, 0, size_to_read)?;
+        self.position += got as u64;
 
-        if size_to_read < size_in_pcap {
+        if got == size_to_read && size_to_read < size_in_pcap {
             // we used to return InvalidPacketSize here, now we just drop the excessive data
-            let mut take = self.reader.by_ref().take((size_in_pcap - size_to_read) as u64);
-            io::copy(&mut take, &mut io::sink())?;
+            let mut take = input.by_ref().take((size_in_pcap - size_to_read) as u64 - discarded);
+            let skipped = discarded + io::copy(&mut take, &mut io::sink())?;
+            self.position += skipped;
+            got += skipped as usize;
         }
+        if got < size_in_pcap {
+            self.eof = true;
+            let data = &state.packet_buffer[..usize::min(got, size_to_read)];
+            return Err(truncated_packet(&rh, extended, &state.file_header, packet, offset, data, size_in_pcap - got));
+        }
+        let buf = &state.packet_buffer[..size_to_read];
 
-        let orig_len = usize::try_from(rh.orig_len).or(Err(PcapError::InvalidPacketSize))?;
+        let orig_len = usize::try_from(rh.orig_len).map_err(|_| invalid_record_size(&rh, packet, offset))?;
 
+        let record = RecordInfo {
+            offset,
+            ts_sec: rh.ts_sec,
+            ts_usec: rh.ts_usec,
+            incl_len: rh.incl_len,
+            orig_len: rh.orig_len,
+            truncated: size_to_read < size_in_pcap,
+        };
         if let Some(t) = rh.get_time(&state.file_header) {
-            Ok(Some(CapturedPacket {
+            Ok(Some((CapturedPacket {
                 time: t,
                 data: buf,
                 orig_len,
+                extended,
+            }, record)))
+        } else {
+            Err(PcapError::InvalidDate)
+        }
+    }
+
+    /// The byte offset of the next record, counted from the start of the file header. The
+    /// position is only tracked while packets are read successfully; it is unreliable after an
+    /// I/O error.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The byte ranges that were skipped in recovery mode (see `ReaderOptions::recover`), in the
+    /// order in which they were skipped. Adjacent ranges are merged.
+    pub fn skipped_ranges(&self) -> &[Range<u64>] {
+        &self.skipped
+    }
+
+    /// In follow mode, reads ahead until the next record is completely available. Returns `false`
+    /// if it isn't yet.
+    pub(crate) fn complete_record_available(&mut self) -> io::Result<bool> {
+        let header_len = self.state.file_header.record_header_len() as usize;
+        if !self.fill_lookahead(header_len)? {
+            return Ok(false);
+        }
+        let (rh, _) = self.peek_header(0)?;
+        // only buffer the packet data that is returned, the rest is skipped as it arrives, so
+        // that implausibly large records don't use up memory
+        let options = self.state.options;
+        let keep = options.packet_size(&rh, &self.state.file_header, self.packets, self.position).unwrap_or(0);
+        if !self.fill_lookahead(header_len + keep)? {
+            return Ok(false);
+        }
+        if options.recover {
+            // implausible records are skipped while resynchronising anyway
+            return Ok(true);
+        }
+        let missing = u64::from(rh.incl_len) - keep as u64 - self.discarded;
+        self.discarded += io::copy(&mut self.reader.by_ref().take(missing), &mut io::sink())?;
+        Ok(keep as u64 + self.discarded == u64::from(rh.incl_len))
+    }
+
+    /// Reads the next record header. Returns `Ok(None)` on EOF, and an error if the file ends in
+    /// the middle of the record header.
+    fn read_header(&mut self) -> Result<Option<(def::PcapRecordHeader, Option<ExtendedRecord>)>, PcapError> {
+        let header_len = self.state.file_header.record_header_len() as usize;
+        let mut buf = [0u8; 24];
+        let got = read_full(&mut (&mut self.lookahead).chain(&mut self.reader), &mut buf[..header_len])?;
+        if got == 0 {
+            return Ok(None);
+        } else if got < header_len {
+            self.eof = true;
+            return Err(PcapError::TruncatedRecord { offset: self.position, expected: header_len, got });
+        }
+        Ok(Some(read_record_header(&mut &buf[..header_len], &self.state.file_header)?))
+    }
+
+    /// Reads the next record header in recovery mode. If it is not plausible, data is skipped
+    /// until a plausible record is found. Returns `Ok(None)` on EOF.
+    fn read_plausible_header(&mut self) -> io::Result<Option<(def::PcapRecordHeader, Option<ExtendedRecord>)>> {
+        let header_len = self.state.file_header.record_header_len() as usize;
+        let mut resync = false;
+        loop {
+            if !self.fill_lookahead(header_len)? {
+                // the rest is too short for a record header
+                let rest = self.lookahead.len();
+                if rest > 0 {
+                    self.skip(rest);
+                }
+                return Ok(None);
+            }
+            let header = self.peek_header(0)?;
+            let options = &self.state.options;
+            let plausible = options.plausible_record(&header.0, &self.state.file_header, self.prev_ts_sec, resync)
+                && (!resync || self.next_record_plausible(&header.0)?);
+            if plausible {
+                self.lookahead.drain(..header_len);
+                return Ok(Some(header));
+            }
+            self.skip(1);
+            resync = true;
+        }
+    }
+
+    /// Checks whether the record that follows the record with the header at the start of the
+    /// lookahead buffer is plausible, or whether that record ends exactly at the end of the file.
+    fn next_record_plausible(&mut self, rh: &def::PcapRecordHeader) -> io::Result<bool> {
+        let header_len = self.state.file_header.record_header_len() as usize;
+        let next = header_len + rh.incl_len as usize;
+        if !self.fill_lookahead(next + header_len)? {
+            return Ok(self.lookahead.len() == next);
+        }
+        let (next_rh, _) = self.peek_header(next)?;
+        Ok(self.state.options.plausible_record(&next_rh, &self.state.file_header, Some(rh.ts_sec), false))
+    }
+
+    /// Parses the record header at `offset` in the lookahead buffer, which has to be filled.
+    fn peek_header(&self, offset: usize) -> io::Result<(def::PcapRecordHeader, Option<ExtendedRecord>)> {
+        let mut buf = [0u8; 24];
+        let header_len = self.state.file_header.record_header_len() as usize;
+        for (b, v) in buf.iter_mut().zip(self.lookahead.range(offset..offset + header_len)) {
+            *b = *v;
+        }
+        read_record_header(&mut &buf[..header_len], &self.state.file_header)
+    }
+
+    /// Reads from the underlying reader until the lookahead buffer contains at least `len` bytes.
+    /// Returns `false` if EOF is reached before that.
+    fn fill_lookahead(&mut self, len: usize) -> io::Result<bool> {
+        let mut buf = [0u8; 4096];
+        while self.lookahead.len() < len {
+            let want = usize::min(buf.len(), len - self.lookahead.len());
+            match self.reader.read(&mut buf[..want]) {
+                Ok(0) => return Ok(false),
+                Ok(read) => self.lookahead.extend(&buf[..read]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Drops `len` bytes from the lookahead buffer, and records them as skipped.
+    fn skip(&mut self, len: usize) {
+        self.lookahead.drain(..len);
+        let end = self.position + len as u64;
+        match self.skipped.last_mut() {
+            Some(range) if range.end == self.position => range.end = end,
+            _ => self.skipped.push(self.position..end),
+        }
+        self.position = end;
+    }
+
+    /// Calls `f` for every remaining packet in the packet capture, stopping at the first error.
+    pub fn for_each<F>(&mut self, mut f: F) -> Result<(), PcapError>
+        where F: FnMut(CapturedPacket)
+    {
+        while let Some(packet) = self.next()? {
+            f(packet);
+        }
+        Ok(())
+    }
+
+    /// Folds every remaining packet in the packet capture into an accumulator, like
+    /// `Iterator::try_fold`. Stops at the first error, either from reading the packet capture or
+    /// returned by `f`.
+    pub fn try_fold<B, E, F>(&mut self, init: B, mut f: F) -> Result<B, E>
+        where F: FnMut(B, CapturedPacket) -> Result<B, E>,
+              E: From<PcapError>
+    {
+        let mut acc = init;
+        while let Some(packet) = self.next()? {
+            acc = f(acc, packet)?;
+        }
+        Ok(acc)
+    }
+
+    /// Converts this `PcapReader` into a standard `Iterator` over the packets. The packet data is
+    /// copied into an `OwnedPacket` for every packet.
+    pub fn into_owned_iter(self) -> OwnedPackets<R> {
+        OwnedPackets { reader: self }
+    }
+
+    /// The detected compression format of the packet capture, or `None` if it is not compressed.
+    pub fn codec(&self) -> Option<Codec> {
+        self.reader.codec()
+    }
+
+    /// Destroys this `PcapReader` and returns access to the underlying `Read`. If the packet
+    /// capture is compressed, any data that was buffered by the decompressor is lost.
+    pub fn take_reader(self) -> R {
+        self.reader.into_inner()
+    }
+
+    /// Gives access to the underlying `Read` and the parsed file header, for reading records
+    /// directly. This resets the EOF state, since the caller may move the reader.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the packet capture is compressed.
+    pub(crate) fn raw_parts(&mut self) -> Result<(&mut R, &def::PcapFileHeader), PcapError> {
+        self.eof = false;
+        self.lookahead.clear();
+        self.discarded = 0;
+        let reader = self.reader.uncompressed_mut().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "compressed packet captures can't be accessed directly")
+        })?;
+        Ok((reader, &self.state.file_header))
+    }
+
+    /// Sets the byte offset that is reported by `position` and the number of the next packet,
+    /// after the caller moved the reader. The timestamp of the previous record is forgotten.
+    pub(crate) fn set_position(&mut self, position: u64, packets: u64) {
+        self.position = position;
+        self.packets = packets;
+        self.prev_ts_sec = None;
+    }
+}
+
+/// An iterator over the packets of a `PcapReader`, created by `PcapReader::into_owned_iter`.
+pub struct OwnedPackets<R: io::Read> {
+    reader: PcapReader<R>,
+}
+impl<R: io::Read> OwnedPackets<R> {
+    /// Destroys this iterator and returns the underlying `PcapReader`.
+    pub fn into_inner(self) -> PcapReader<R> {
+        self.reader
+    }
+}
+impl<R: io::Read> Iterator for OwnedPackets<R> {
+    type Item = Result<OwnedPacket, PcapError>;
+
+    /// Returns `None` on EOF, or a packet as long as one is available.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next().map(|packet| packet.as_ref().map(OwnedPacket::from)).transpose()
+    }
+}
+
+/// Reads into `buf` until it is full or EOF is reached. Returns the number of bytes that were read.
+fn read_full<R: io::Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut got = 0;
+    while got < buf.len() {
+        match reader.read(&mut buf[got..]) {
+            Ok(0) => break,
+            Ok(read) => got += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(got)
+}
+
+/// Makes room in `buffer` for reading more of a packet with `len` bytes, of which the first `got`
+/// bytes were read already, and returns the end of the space to read into.
+///
+/// The buffer grows by at most `BUFFER_CHUNK` or the amount that was already read, whichever is
+/// larger, so that a record header that claims a huge length can't make the reader allocate much
+/// more memory than there is data.
+pub(crate) fn grow_buffer(buffer: &mut Vec<u8>, got: usize, len: usize) -> usize {
+    if got >= buffer.len() && got < len {
+        let grow = usize::min(len - got, usize::max(BUFFER_CHUNK, got));
+        buffer.resize(got + grow, 0);
+    }
+    usize::min(len, buffer.len())
+}
+
+/// Reads the rest of a packet with `len` bytes into `buffer`, after the first `got` bytes that
+/// were read already, growing the buffer as data arrives. Returns the number of bytes in the
+/// buffer, which is less than `len` at EOF.
+fn read_into_buffer<R: io::Read>(reader: &mut R, buffer: &mut Vec<u8>, mut got: usize, len: usize) -> io::Result<usize> {
+    while got < len {
+        let end = grow_buffer(buffer, got, len);
+        got += read_full(reader, &mut buffer[got..end])?;
+        if got < end {
+            break;
+        }
+    }
+    Ok(got)
+}
+
+/// Creates the error for a record that is rejected because of its size. `packet` and `offset` are
+/// the number and the position of the record.
+pub(crate) fn invalid_record_size(rh: &def::PcapRecordHeader, packet: u64, offset: u64) -> PcapError {
+    PcapError::InvalidRecordSize { packet, offset, incl_len: rh.incl_len, orig_len: rh.orig_len }
+}
+
+/// Creates the error for a record whose packet data is cut off after `data` by the end of the
+/// file. `packet` and `offset` are the number and the position of the record.
+pub(crate) fn truncated_packet(rh: &def::PcapRecordHeader, extended: Option<ExtendedRecord>, file_header: &def::PcapFileHeader, packet: u64, offset: u64, data: &[u8], missing: usize) -> PcapError {
+    let (time, orig_len) = match (rh.get_time(file_header), usize::try_from(rh.orig_len)) {
+        (Some(time), Ok(orig_len)) => (time, orig_len),
+        (None, _) => return PcapError::InvalidDate,
+        (_, Err(_)) => return invalid_record_size(rh, packet, offset),
+    };
+    let packet = OwnedPacket {
+        time,
+        data: data.to_vec(),
+        orig_len,
+        interface_id: None,
+        flags: None,
+        extended,
+    };
+    PcapError::TruncatedPacket(Box::new(TruncatedPacket { offset, packet, missing }))
+}
+
+/// Reads and parses the file header of a packet capture in the traditional format.
+/// Fails with `PcapError::TruncatedFileHeader` if the input ends before the end of the header.
+pub(crate) fn read_file_header<R: io::Read>(reader: &mut R) -> Result<(FileOptions, def::PcapFileHeader), PcapError> {
+    let mut buf = [0u8; def::PcapFileHeader::LEN as usize];
+    let got = read_full(reader, &mut buf)?;
+    if got < buf.len() {
+        return Err(PcapError::TruncatedFileHeader { expected: buf.len(), got });
+    }
+    let fh : def::PcapFileHeaderInFile = NativeUnpacker::unpack(&mut &buf[..])?;
+    let fh = def::PcapFileHeader::try_from(fh)?;
+
+    let (linktype, fcs_length, linktype_reserved) = def::split_network(fh.network);
+    let opts = FileOptions {
+        snaplen: fh.snaplen,
+        linktype,
+        fcs_length,
+        linktype_reserved,
+        high_res_timestamps: fh.ns_res,
+        variant: fh.variant,
+        non_native_byte_order: fh.need_byte_swap,
+        utc_offset: fh.utc_offset,
+        sigfigs: fh.sigfigs,
+    };
+    Ok((opts, fh))
+}
+
+/// Reads a record header in the byte order of the file, including the additional fields of
+/// extended records.
+pub(crate) fn read_record_header<R: io::Read>(reader: &mut R, file_header: &def::PcapFileHeader) -> io::Result<(def::PcapRecordHeader, Option<ExtendedRecord>)> {
+    fn unpack<T: Packed, R: io::Read>(reader: &mut R, file_header: &def::PcapFileHeader) -> io::Result<T> {
+        if file_header.need_byte_swap {
+            NonNativeUnpacker::unpack::<T>(reader)
+        } else {
+            NativeUnpacker::unpack::<T>(reader)
+        }
+    }
+
+    let rh = unpack::<def::PcapRecordHeader, R>(reader, file_header)?;
+    let extended = match file_header.variant {
+        def::PcapVariant::Modified => Some(unpack::<def::PcapExtendedRecordHeader, R>(reader, file_header)?.into()),
+        def::PcapVariant::Nokia => {
+            reader.read_exact(&mut [0; def::NOKIA_RECORD_PADDING])?;
+            None
+        },
+        _ => None,
+    };
+    Ok((rh, extended))
+}
+
+
+/// The `PcapSliceReader` struct allows reading packets from a packet capture that is completely
+/// available in memory, e.g. because it was memory-mapped with the
+/// [memmap2](https://crates.io/crates/memmap2) crate.
+///
+/// In contrast to `PcapReader`, the returned packets borrow directly from the input data instead
+/// of an internal buffer. No data is copied, and packets can be kept around for as long as the
+/// input data lives. This makes `PcapSliceReader` a normal `Iterator`.
+pub struct PcapSliceReader<'a> {
+    data: &'a [u8],
+    file_header: def::PcapFileHeader,
+    options: ReaderOptions,
+    /// The byte offset of `data` in the file.
+    offset: u64,
+    /// The number of records that were read so far.
+    packets: u64,
+}
+
+impl<'a> PcapSliceReader<'a> {
+    /// Create a new `PcapSliceReader` that reads the packet capture data from the specified slice.
+    pub fn new(data: &'a [u8]) -> Result<(FileOptions, Self), PcapError> {
+        PcapSliceReader::with_options(data, ReaderOptions::default())
+    }
+
+    /// Create a new `PcapSliceReader` like `new`, but with the specified limits and validation
+    /// options.
+    pub fn with_options(mut data: &'a [u8], options: ReaderOptions) -> Result<(FileOptions, Self), PcapError> {
+        let (mut opts, mut file_header) = read_file_header(&mut data)?;
+        options.apply_to_file_header(&mut opts, &mut file_header)?;
+        Ok((opts, PcapSliceReader { data, file_header, options, offset: def::PcapFileHeader::LEN, packets: 0 }))
+    }
+
+    /// Reads the next packet. Returns `Ok(None)` on EOF.
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket<'a>>, PcapError> {
+        let mut rest = self.data;
+        let header_len = self.file_header.record_header_len() as usize;
+        if rest.len() < header_len {
+            self.data = &[];
+            return if rest.is_empty() {
+                Ok(None)
+            } else {
+                Err(PcapError::TruncatedRecord { offset: self.offset, expected: header_len, got: rest.len() })
+            };
+        }
+        let (rh, extended) = read_record_header(&mut rest, &self.file_header)?;
+
+        let offset = self.offset;
+        let packet = self.packets;
+        self.packets += 1;
+
+        let size_in_pcap = usize::try_from(rh.incl_len).map_err(|_| invalid_record_size(&rh, packet, offset))?;
+        if rest.len() < size_in_pcap {
+            self.data = &[];
+            let size_to_read = self.options.packet_size(&rh, &self.file_header, packet, offset)?;
+            let data = &rest[..usize::min(rest.len(), size_to_read)];
+            return Err(truncated_packet(&rh, extended, &self.file_header, packet, offset, data, size_in_pcap - rest.len()));
+        }
+        self.data = &rest[size_in_pcap..];
+        self.offset += self.file_header.record_header_len() + size_in_pcap as u64;
+        let size_to_read = self.options.packet_size(&rh, &self.file_header, packet, offset)?;
+        let data = &rest[..size_to_read];
+
+        let orig_len = usize::try_from(rh.orig_len).map_err(|_| invalid_record_size(&rh, packet, offset))?;
+
+        if let Some(t) = rh.get_time(&self.file_header) {
+            Ok(Some(CapturedPacket {
+                time: t,
+                data,
+                orig_len,
+                extended,
             }))
         } else {
             Err(PcapError::InvalidDate)
         }
     }
 
-    /// Destroys this `PcapReader` and returns access to the underlying `Read`.
+    /// The part of the input data that was not read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+impl<'a> Iterator for PcapSliceReader<'a> {
+    type Item = Result<CapturedPacket<'a>, PcapError>;
+
+    /// Returns `None` on EOF, or a packet as long as one is available.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+/// Upper limit for the size of a pcapng block, to avoid huge memory allocations for corrupted
+/// files.
+const MAX_NG_BLOCK_SIZE: usize = 0x6000_0000;
+
+/// A packet read from a pcapng file, along with the interface it was captured on.
+#[derive(Eq,PartialEq,Debug)]
+pub struct InterfacePacket<'a> {
+    /// The index of the interface in the current section of the file.
+    pub interface_id: u32,
+    /// The options of the interface the packet was captured on.
+    pub interface: InterfaceOptions,
+    /// The link-layer flags of the packet (the `epb_flags` option), if they were recorded.
+    pub flags: Option<u32>,
+    /// The packet itself.
+    pub packet: CapturedPacket<'a>,
+}
+
+/// The `PcapNgReader` struct allows reading packets from a packet capture in the pcapng format.
+///
+/// Section Header Blocks, Interface Description Blocks, Enhanced Packet Blocks, Simple Packet
+/// Blocks and the obsolete Packet Blocks are understood, all other blocks are skipped.
+pub struct PcapNgReader<R> {
+    reader: R,
+    state: Option<PcapNgState>,
+}
+struct PcapNgState {
+    need_byte_swap: bool,
+    interfaces: Vec<InterfaceOptions>,
+    block_buffer: Vec<u8>,
+    block_len: usize,
+    /// The byte offset of the next block, counted from the start of the file.
+    position: u64,
+}
+impl PcapNgState {
+    fn body(&self) -> def::NgBlockBody<'_> {
+        def::NgBlockBody {
+            data: &self.block_buffer[..self.block_len],
+            need_byte_swap: self.need_byte_swap,
+        }
+    }
+}
+
+impl<R: io::Read> PcapNgReader<R> {
+    /// Create a new `PcapNgReader` that reads the packet capture data from the specified `Reader`.
+    ///
+    /// The data has to start with a Section Header Block.
+    pub fn new(reader: R) -> Result<Self, PcapError> {
+        let mut pcapr = PcapNgReader {
+            reader,
+            state: Some(PcapNgState {
+                need_byte_swap: false,
+                interfaces: Vec::new(),
+                block_buffer: Vec::new(),
+                block_len: 0,
+                position: 0,
+            }),
+        };
+        match pcapr.read_block()? {
+            Some(def::NG_BLOCK_SECTION_HEADER) => Ok(pcapr),
+            Some(block_type) => Err(PcapError::BadMagic(block_type)),
+            None => Err(PcapError::InvalidFileHeader),
+        }
+    }
+
+    /// Reads the next block into the block buffer and returns its type. Section Header Blocks are
+    /// parsed right away, because they determine how the following blocks are read.
+    ///
+    /// Returns `Ok(None)` on EOF.
+    fn read_block(&mut self) -> Result<Option<u32>, PcapError> {
+        let state = match self.state.as_mut() {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+
+        let mut head = [0u8; 12];
+        let got = read_full(&mut self.reader, &mut head[..8])?;
+        if got < 8 {
+            let offset = state.position;
+            self.state = None;
+            return if got == 0 {
+                Ok(None)
+            } else {
+                Err(PcapError::TruncatedRecord { offset, expected: 8, got })
+            };
+        }
+        let mut head_len = 8;
+
+        let block_type = u32::from_ne_bytes([head[0], head[1], head[2], head[3]]);
+        if block_type == def::NG_BLOCK_SECTION_HEADER {
+            // a new section might use a different byte order
+            self.reader.read_exact(&mut head[8..])?;
+            head_len = 12;
+            let bom = u32::from_ne_bytes([head[8], head[9], head[10], head[11]]);
+            state.need_byte_swap = if bom == def::NG_BYTE_ORDER_MAGIC {
+                false
+            } else if bom.swap_bytes() == def::NG_BYTE_ORDER_MAGIC {
+                true
+            } else {
+                return Err(PcapError::InvalidFileHeader);
+            };
+        }
+        let head_body = def::NgBlockBody { data: &head, need_byte_swap: state.need_byte_swap };
+        let block_type = head_body.u32(0).unwrap();
+        let total_len = usize::try_from(head_body.u32(4).unwrap()).or(Err(PcapError::InvalidPacketSize))?;
+
+        let min_len = if block_type == def::NG_BLOCK_SECTION_HEADER {
+            def::NG_BLOCK_OVERHEAD + 16
+        } else {
+            def::NG_BLOCK_OVERHEAD
+        };
+        if total_len % 4 != 0 || total_len < min_len || total_len > MAX_NG_BLOCK_SIZE {
+            return Err(PcapError::InvalidPacketSize);
+        }
+        let body_len = total_len - def::NG_BLOCK_OVERHEAD;
+        state.block_len = body_len;
+
+        let already_read = head_len - 8;
+        grow_buffer(&mut state.block_buffer, 0, already_read);
+        state.block_buffer[..already_read].copy_from_slice(&head[8..head_len]);
+        if read_into_buffer(&mut self.reader, &mut state.block_buffer, already_read, body_len)? < body_len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let mut trailer = [0u8; 4];
+        self.reader.read_exact(&mut trailer)?;
+        let trailer_body = def::NgBlockBody { data: &trailer, need_byte_swap: state.need_byte_swap };
+        if trailer_body.u32(0) != Some(total_len as u32) {
+            return Err(PcapError::InvalidPacketSize);
+        }
+        state.position += total_len as u64;
+
+        if block_type == def::NG_BLOCK_SECTION_HEADER {
+            let body = state.body();
+            match (body.u16(4), body.u16(6)) {
+                (Some(def::NG_VERSION_MAJOR), _) => {},
+                (Some(major), Some(minor)) => return Err(PcapError::UnsupportedVersion { major, minor }),
+                _ => return Err(PcapError::InvalidFileHeader),
+            }
+            state.interfaces.clear();
+        }
+
+        Ok(Some(block_type))
+    }
+
+    /// This function allows iterating over the packets in the packet capture, in a similar fashion
+    /// to normal iterators. (The exact interface is unfortunately incompatible.)
+    ///
+    /// Returns `Ok(None)` on EOF, or a packet as long as one is available. Simple Packet Blocks
+    /// carry no timestamp, packets from them are reported at the time of the UNIX epoch.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<InterfacePacket<'_>>, PcapError> {
+        let block_type = loop {
+            match self.read_block()? {
+                None => return Ok(None),
+                Some(def::NG_BLOCK_INTERFACE_DESCRIPTION) => {
+                    let state = self.state.as_mut().unwrap();
+                    let iface = def::ng_parse_interface(&state.body()).ok_or(PcapError::InvalidFileHeader)?;
+                    state.interfaces.push(iface);
+                },
+                Some(block_type @ def::NG_BLOCK_ENHANCED_PACKET) |
+                Some(block_type @ def::NG_BLOCK_PACKET) |
+                Some(block_type @ def::NG_BLOCK_SIMPLE_PACKET) => break block_type,
+                Some(_) => {},
+            }
+        };
+        let state = self.state.as_ref().unwrap();
+        let body = state.body();
+
+        let (interface_id, timestamp, incl_len, orig_len, data_offset) = match block_type {
+            def::NG_BLOCK_ENHANCED_PACKET => (
+                body.u32(0),
+                body.timestamp(4),
+                body.u32(12),
+                body.u32(16),
+                20,
+            ),
+            def::NG_BLOCK_PACKET => (
+                body.u16(0).map(u32::from),
+                body.timestamp(4),
+                body.u32(12),
+                body.u32(16),
+                20,
+            ),
+            _ => {
+                // the captured length of simple packets is implied by the block length
+                let orig_len = body.u32(0);
+                let incl_len = orig_len.map(|len| u32::min(len, (state.block_len - 4) as u32));
+                (Some(0), Some(0), incl_len, orig_len, 4)
+            },
+        };
+        let interface_id = interface_id.ok_or(PcapError::InvalidPacketSize)?;
+        let interface = *usize::try_from(interface_id).ok()
+            .and_then(|id| state.interfaces.get(id))
+            .ok_or(PcapError::InvalidFileHeader)?;
+
+        let incl_len = incl_len.and_then(|len| usize::try_from(len).ok()).ok_or(PcapError::InvalidPacketSize)?;
+        let orig_len = orig_len.and_then(|len| usize::try_from(len).ok()).ok_or(PcapError::InvalidPacketSize)?;
+        let mut data = body.slice(data_offset, incl_len).ok_or(PcapError::InvalidPacketSize)?;
+
+        let flags = if block_type == def::NG_BLOCK_SIMPLE_PACKET {
+            None
+        } else {
+            body.options(data_offset + def::ng_padded(incl_len))
+                .find(|&(code, _)| code == def::NG_OPT_EPB_FLAGS)
+                .and_then(|(_, value)| def::NgBlockBody { data: value, need_byte_swap: body.need_byte_swap }.u32(0))
+        };
+
+        if interface.snaplen != 0 && data.len() > interface.snaplen {
+            data = &data[..interface.snaplen];
+        }
+
+        let timestamp = timestamp.ok_or(PcapError::InvalidPacketSize)?;
+        let time = def::ng_get_time(timestamp, &interface).ok_or(PcapError::InvalidDate)?;
+
+        Ok(Some(InterfacePacket {
+            interface_id,
+            interface,
+            flags,
+            packet: CapturedPacket {
+                time,
+                data,
+                orig_len,
+                extended: None,
+            },
+        }))
+    }
+
+    /// The interfaces that were described in the current section of the file so far.
+    pub fn interfaces(&self) -> &[InterfaceOptions] {
+        self.state.as_ref().map_or(&[], |state| &state.interfaces[..])
+    }
+
+    /// Destroys this `PcapNgReader` and returns access to the underlying `Read`.
     pub fn take_reader(self) -> R {
         self.reader
     }
 }
 
+/// The format of a packet capture, as detected by `open_any`.
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub enum Format {
+    /// The traditional libpcap format, with the options from the file header.
+    Pcap(FileOptions),
+    /// The pcapng format.
+    PcapNg,
+}
+
+/// The reader passed to the format-specific readers by `open_any`, which replays the bytes that
+/// were needed to detect the format.
+type Sniffed<R> = io::Chain<io::Cursor<[u8; 4]>, Decompress<R>>;
+
+/// A reader for packet captures in any of the supported formats, created with `open_any`.
+pub struct AnyReader<R: io::Read> {
+    inner: AnyReaderInner<R>,
+    codec: Option<Codec>,
+}
+#[allow(clippy::large_enum_variant)]
+enum AnyReaderInner<R: io::Read> {
+    Pcap(PcapReader<Sniffed<R>>, FileOptions, InterfaceOptions),
+    PcapNg(PcapNgReader<Sniffed<R>>),
+}
+
+/// Create a reader for the packet capture data from the specified `Reader`, detecting its
+/// format from the magic number at the start. Compressed captures are decompressed
+/// transparently, if the cargo feature for their compression format is enabled.
+///
+/// Returns `PcapError::BadMagic` if the format is not supported.
+pub fn open_any<R: io::Read>(reader: R) -> Result<AnyReader<R>, PcapError> {
+    let mut reader = Decompress::new(reader)?;
+    let codec = reader.codec();
+    let mut magic = [0u8; 4];
+    let got = read_full(&mut reader, &mut magic)?;
+    if got < magic.len() {
+        return Err(PcapError::TruncatedFileHeader { expected: magic.len(), got });
+    }
+    let reader = io::Cursor::new(magic).chain(reader);
+
+    let magic = u32::from_ne_bytes(magic);
+    let inner = if magic == def::PCAPNG_MAGIC {
+        AnyReaderInner::PcapNg(PcapNgReader::new(reader)?)
+    } else if def::PcapMagic::try_from(magic).is_ok() {
+        let (opts, reader) = PcapReader::new(reader)?;
+        let iface = InterfaceOptions {
+            snaplen: opts.snaplen,
+            linktype: opts.linktype,
+            ts_resolution: opts.resolution(),
+            ts_offset: i64::from(reader.state.file_header.utc_offset),
+        };
+        AnyReaderInner::Pcap(reader, opts, iface)
+    } else {
+        return Err(PcapError::BadMagic(magic));
+    };
+    Ok(AnyReader { inner, codec })
+}
+
+impl<R: io::Read> AnyReader<R> {
+    /// The detected format of the packet capture.
+    pub fn format(&self) -> Format {
+        match self.inner {
+            AnyReaderInner::Pcap(_, opts, _) => Format::Pcap(opts),
+            AnyReaderInner::PcapNg(_) => Format::PcapNg,
+        }
+    }
+
+    /// The detected compression format of the packet capture, or `None` if it is not compressed.
+    pub fn codec(&self) -> Option<Codec> {
+        self.codec
+    }
+
+    /// This function allows iterating over the packets in the packet capture, in a similar fashion
+    /// to normal iterators. (The exact interface is unfortunately incompatible.)
+    ///
+    /// Returns `Ok(None)` on EOF, or a packet as long as one is available. Packets from files in
+    /// the traditional format are reported as captured on interface `0`, with the options from
+    /// the file header.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<InterfacePacket<'_>>, PcapError> {
+        match self.inner {
+            AnyReaderInner::Pcap(ref mut reader, _, interface) => Ok(reader.next()?.map(|packet| {
+                InterfacePacket {
+                    interface_id: 0,
+                    interface,
+                    flags: None,
+                    packet,
+                }
+            })),
+            AnyReaderInner::PcapNg(ref mut reader) => reader.next(),
+        }
+    }
+
+    /// Destroys this `AnyReader` and returns access to the underlying `Read`.
+    pub fn take_reader(self) -> R {
+        match self.inner {
+            AnyReaderInner::Pcap(reader, _, _) => reader.take_reader().into_inner().1.into_inner(),
+            AnyReaderInner::PcapNg(reader) => reader.take_reader().into_inner().1.into_inner(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -151,4 +1206,568 @@ mod test {
             assert!(r.next().unwrap().is_none());
         }
     }
+
+    /// Builds a pcapng block with the given byte order.
+    fn ng_block(block_type: u32, body: &[u8], big_endian: bool) -> Vec<u8> {
+        let conv = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let total_len = 12 + ((body.len() + 3) & !3) as u32;
+        let mut block = Vec::new();
+        block.extend_from_slice(&conv(block_type));
+        block.extend_from_slice(&conv(total_len));
+        block.extend_from_slice(body);
+        block.resize(total_len as usize - 4, 0);
+        block.extend_from_slice(&conv(total_len));
+        block
+    }
+
+    /// Builds a pcapng file with two interfaces and some packets.
+    fn ng_file(big_endian: bool) -> Vec<u8> {
+        let u16b = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u32b = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&u32b(0x1a2b_3c4d));
+        shb.extend_from_slice(&u16b(1));
+        shb.extend_from_slice(&u16b(0));
+        shb.extend_from_slice(&[0xff; 8]);
+
+        // ethernet, snaplen 4, default resolution
+        let mut idb0 = Vec::new();
+        idb0.extend_from_slice(&u16b(1));
+        idb0.extend_from_slice(&[0, 0]);
+        idb0.extend_from_slice(&u32b(4));
+
+        // raw IP, no snaplen, nanosecond resolution
+        let mut idb1 = Vec::new();
+        idb1.extend_from_slice(&u16b(101));
+        idb1.extend_from_slice(&[0, 0]);
+        idb1.extend_from_slice(&u32b(0));
+        idb1.extend_from_slice(&u16b(9));
+        idb1.extend_from_slice(&u16b(1));
+        idb1.extend_from_slice(&[9, 0, 0, 0]);
+        idb1.extend_from_slice(&[0, 0, 0, 0]);
+
+        let epb = |iface: u32, ts: u64, data: &[u8], flags: Option<u32>| {
+            let mut epb = Vec::new();
+            epb.extend_from_slice(&u32b(iface));
+            epb.extend_from_slice(&u32b((ts >> 32) as u32));
+            epb.extend_from_slice(&u32b(ts as u32));
+            epb.extend_from_slice(&u32b(data.len() as u32));
+            epb.extend_from_slice(&u32b(data.len() as u32 + 10));
+            epb.extend_from_slice(data);
+            if let Some(flags) = flags {
+                epb.resize((epb.len() + 3) & !3, 0);
+                epb.extend_from_slice(&u16b(2));
+                epb.extend_from_slice(&u16b(4));
+                epb.extend_from_slice(&u32b(flags));
+                epb.extend_from_slice(&[0, 0, 0, 0]);
+            }
+            epb
+        };
+
+        let mut file = Vec::new();
+        file.extend(ng_block(0x0a0d_0d0a, &shb, big_endian));
+        file.extend(ng_block(1, &idb0, big_endian));
+        file.extend(ng_block(0x0bad, &[1, 2, 3, 4, 5], big_endian));
+        file.extend(ng_block(1, &idb1, big_endian));
+        file.extend(ng_block(6, &epb(0, 1_500_000_250_000, b"DATA!", None), big_endian));
+        file.extend(ng_block(6, &epb(1, 1_500_000_000_000_000_123, b"IP", Some(0x8001)), big_endian));
+        file
+    }
+
+    #[test]
+    /// Reads pcapng files in both byte orders.
+    fn pcapng() {
+        #[cfg(not(feature = "time"))]
+        use std::time::{Duration, UNIX_EPOCH};
+
+        for big_endian in [false, true] {
+            let file = ng_file(big_endian);
+            let mut reader = PcapNgReader::new(&file[..]).unwrap();
+
+            let p = reader.next().unwrap().unwrap();
+            assert_eq!(p.interface_id, 0);
+            assert_eq!(p.interface.linktype, crate::Linktype::ETHERNET);
+            assert_eq!(p.packet.data, b"DATA");
+            assert_eq!(p.packet.orig_len, 15);
+            assert_eq!(p.flags, None);
+            #[cfg(not(feature = "time"))]
+            assert_eq!(p.packet.time, UNIX_EPOCH + Duration::new(1_500_000, 250_000_000));
+
+            let p = reader.next().unwrap().unwrap();
+            assert_eq!(p.interface_id, 1);
+            assert_eq!(p.interface.linktype, crate::Linktype::RAW);
+            assert_eq!(p.interface.ts_resolution, crate::Resolution::NANOSECONDS);
+            assert_eq!(p.packet.data, b"IP");
+            assert_eq!(p.flags, Some(0x8001));
+            #[cfg(not(feature = "time"))]
+            assert_eq!(p.packet.time, UNIX_EPOCH + Duration::new(1_500_000_000, 123));
+
+            assert!(reader.next().unwrap().is_none());
+
+            // a block header that is cut off is not the end of the file
+            let mut truncated = file.clone();
+            truncated.extend_from_slice(&[6, 0, 0]);
+            let mut reader = PcapNgReader::new(&truncated[..]).unwrap();
+            reader.next().unwrap().unwrap();
+            reader.next().unwrap().unwrap();
+            let offset = file.len() as u64;
+            assert!(matches!(reader.next(), Err(PcapError::TruncatedRecord { offset: o, expected: 8, got: 3 }) if o == offset));
+            assert!(reader.next().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    /// Makes sure `open_any` detects both formats.
+    fn detect_format() {
+        let pcap = [0x4du8, 0x3c, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x65, 0x00, 0x00, 0x00, 0x1a,
+                    0x6e, 0x50, 0x56, 0xd0, 0x0a, 0x2b, 0x18, 0x04, 0x00, 0x00, 0x00, 0x3c, 0x00,
+                    0x00, 0x00, 0x44, 0x41, 0x54, 0x41];
+        let mut reader = open_any(&pcap[..]).unwrap();
+        assert!(matches!(reader.format(), Format::Pcap(FileOptions { linktype: crate::Linktype::RAW, .. })));
+        let p = reader.next().unwrap().unwrap();
+        assert_eq!(p.interface.linktype, crate::Linktype::RAW);
+        assert_eq!(p.packet.data, b"DATA");
+        assert!(reader.next().unwrap().is_none());
+
+        let pcapng = ng_file(true);
+        let mut reader = open_any(&pcapng[..]).unwrap();
+        assert_eq!(reader.format(), Format::PcapNg);
+        assert_eq!(reader.next().unwrap().unwrap().packet.data, b"DATA");
+
+        let magic = u32::from_ne_bytes(*b"GIF8");
+        assert!(matches!(open_any(&b"GIF89a"[..]), Err(PcapError::BadMagic(m)) if m == magic));
+    }
+
+    #[test]
+    /// Makes sure the slice reader returns the same packets as the normal reader, and that they
+    /// can be held at the same time.
+    fn slice_reader() {
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        for i in 0..5u8 {
+            pcap.extend_from_slice(&[i, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, i, i, i, i]);
+        }
+
+        let (opts, mut reader) = PcapReader::new(&pcap[..]).unwrap();
+        let (slice_opts, slice_reader) = PcapSliceReader::new(&pcap).unwrap();
+        assert_eq!(opts, slice_opts);
+
+        let packets: Vec<CapturedPacket> = slice_reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(packets.len(), 5);
+        for (i, p) in packets.iter().enumerate() {
+            assert_eq!(p, &reader.next().unwrap().unwrap());
+            assert_eq!(p.data, &[i as u8; 3]);
+        }
+        assert!(reader.next().unwrap().is_none());
+        assert_ne!(packets[0], packets[1]);
+
+    }
+
+    #[test]
+    /// Makes sure a record that is cut off by the end of the file is reported with its partial
+    /// data, and a partial record header is not mistaken for the end of the file.
+    fn truncated_records() {
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        pcap.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 1, 1, 1, 1]);
+        pcap.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 6, 0, 0, 0, 2, 2, 2, 2]);
+
+        let check_packet = |err: PcapError| match err {
+            PcapError::TruncatedPacket(truncated) => {
+                assert_eq!(truncated.offset, 44);
+                assert_eq!(truncated.missing, 1);
+                assert_eq!(truncated.packet.data, &[2, 2, 2]);
+                assert_eq!(truncated.packet.orig_len, 6);
+            },
+            err => panic!("unexpected error {:?}", err),
+        };
+        let short_data = &pcap[..pcap.len() - 1];
+        let (_, mut reader) = PcapReader::new(short_data).unwrap();
+        reader.next().unwrap().unwrap();
+        check_packet(reader.next().err().unwrap());
+        assert!(reader.next().unwrap().is_none());
+        let (_, mut reader) = PcapSliceReader::new(short_data).unwrap();
+        reader.next().unwrap().unwrap();
+        check_packet(reader.next().unwrap().err().unwrap());
+        assert!(reader.next().is_none());
+
+        let short_header = &pcap[..44 + 5];
+        let (_, mut reader) = PcapReader::new(short_header).unwrap();
+        reader.next().unwrap().unwrap();
+        assert!(matches!(reader.next(), Err(PcapError::TruncatedRecord { offset: 44, expected: 16, got: 5 })));
+        assert!(reader.next().unwrap().is_none());
+        let (_, mut reader) = PcapSliceReader::new(short_header).unwrap();
+        reader.next().unwrap().unwrap();
+        assert!(matches!(reader.next(), Some(Err(PcapError::TruncatedRecord { offset: 44, expected: 16, got: 5 }))));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    /// Makes sure the packet buffer only grows as needed, and the packet size limit is enforced.
+    fn lazy_packet_buffer() {
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x65, 0x00, 0x00,
+                            0x00];
+        for len in [4u8, 20, 8] {
+            pcap.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, len, 0, 0, 0, len, 0, 0, 0]);
+            pcap.extend(std::iter::repeat_n(len, usize::from(len)));
+        }
+
+        let options = ReaderOptions::new().max_packet_size(10);
+        let (opts, mut reader) = PcapReader::with_options(&pcap[..], options).unwrap();
+        assert_eq!(opts.snaplen, 0xffff_ffff);
+        assert_eq!(reader.next().unwrap().unwrap().data, &[4; 4]);
+        assert_eq!(reader.state.packet_buffer.len(), 4);
+        assert!(matches!(reader.next(),
+                         Err(PcapError::InvalidRecordSize { packet: 1, offset: 44, incl_len: 20, orig_len: 20 })));
+        assert_eq!(reader.next().unwrap().unwrap().data, &[8; 8]);
+        assert_eq!(reader.state.packet_buffer.len(), 8);
+        assert!(reader.next().unwrap().is_none());
+
+        // a record that claims 1 GiB of data, followed by only 100 bytes
+        pcap.truncate(24);
+        pcap.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0, 0, 0x40]);
+        pcap.extend_from_slice(&[1; 100]);
+        let (_, mut reader) = PcapReader::new(&pcap[..]).unwrap();
+        match reader.next() {
+            Err(PcapError::TruncatedPacket(truncated)) => assert_eq!(truncated.packet.data, &[1; 100][..]),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(reader.state.packet_buffer.len() <= BUFFER_CHUNK);
+    }
+
+    #[test]
+    /// Makes sure the validation options are applied.
+    fn reader_options() {
+        // snaplen 4, packets with 6 bytes of data and orig_len 6, and 2 bytes with orig_len 1
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        pcap.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 6, 0, 0, 0, 1, 2, 3, 4, 5, 6]);
+        pcap.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 7, 8]);
+
+        let read_all = |options: ReaderOptions| {
+            let (_, mut reader) = PcapReader::with_options(&pcap[..], options).unwrap();
+            let (_, slice_reader) = PcapSliceReader::with_options(&pcap[..], options).unwrap();
+            let mut results = Vec::new();
+            for slice_result in slice_reader {
+                let result = reader.next().transpose().unwrap().map(|p| p.data.to_vec());
+                assert_eq!(format!("{:?}", result), format!("{:?}", slice_result.map(|p| p.data.to_vec())));
+                results.push(result.ok());
+            }
+            assert!(reader.next().unwrap().is_none());
+            results
+        };
+
+        assert_eq!(read_all(ReaderOptions::new()), [Some(vec![1, 2, 3, 4]), Some(vec![7, 8])]);
+        assert_eq!(read_all(ReaderOptions::new().oversized_packets(OversizedPackets::Keep)),
+                   [Some(vec![1, 2, 3, 4, 5, 6]), Some(vec![7, 8])]);
+        assert_eq!(read_all(ReaderOptions::new().oversized_packets(OversizedPackets::Error)),
+                   [None, Some(vec![7, 8])]);
+        assert_eq!(read_all(ReaderOptions::new().reject_truncated_orig_len(true)),
+                   [Some(vec![1, 2, 3, 4]), None]);
+
+        // the rejected records are reported with their position and lengths
+        let options = ReaderOptions::new().oversized_packets(OversizedPackets::Error);
+        let (_, mut reader) = PcapReader::with_options(&pcap[..], options).unwrap();
+        assert!(matches!(reader.next(), Err(PcapError::InvalidRecordSize { packet: 0, offset: 24, incl_len: 6, orig_len: 6 })));
+        let options = ReaderOptions::new().reject_truncated_orig_len(true);
+        let (_, mut reader) = PcapSliceReader::with_options(&pcap[..], options).unwrap();
+        reader.next().unwrap().unwrap();
+        assert!(matches!(reader.next(), Some(Err(PcapError::InvalidRecordSize { packet: 1, offset: 46, incl_len: 2, orig_len: 1 }))));
+
+        assert!(matches!(PcapReader::with_options(&pcap[..], ReaderOptions::new().max_snaplen(3)),
+                         Err(PcapError::SnaplenTooLarge { snaplen: 4, max: 3 })));
+    }
+
+    #[test]
+    /// Makes sure invalid file headers are reported with the offending values.
+    fn file_header_errors() {
+        let pcap = [0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00, 0x00];
+        let err = PcapReader::new(&pcap[..]).err().unwrap();
+        assert!(matches!(err, PcapError::UnsupportedVersion { major: 2, minor: 5 }));
+        assert_eq!(err.to_string(), "The pcap file has the unsupported format version 2.5.");
+
+        let shifted = [&pcap[4..], &[0; 4]].concat();
+        let err = PcapSliceReader::new(&shifted).err().unwrap();
+        assert!(matches!(err, PcapError::BadMagic(m) if m == u32::from_ne_bytes([2, 0, 5, 0])));
+
+        // files that end in the file header
+        assert!(matches!(PcapReader::new(&pcap[..10]).err().unwrap(), PcapError::TruncatedFileHeader { expected: 24, got: 10 }));
+        assert!(matches!(PcapSliceReader::new(&pcap[..23]).err().unwrap(), PcapError::TruncatedFileHeader { expected: 24, got: 23 }));
+        assert!(matches!(open_any(&pcap[..3]).err().unwrap(), PcapError::TruncatedFileHeader { expected: 4, got: 3 }));
+        let err = open_any(&[][..]).err().unwrap();
+        assert!(matches!(err, PcapError::TruncatedFileHeader { expected: 4, got: 0 }));
+        assert_eq!(err.to_string(), "The file header is truncated: expected 4 bytes, got 0.");
+    }
+
+    #[test]
+    /// Makes sure the record metadata reports the offsets and raw fields of every record.
+    fn record_info() {
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        pcap.extend_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0, 6, 0, 0, 0, 9, 0, 0, 0, 1, 2, 3, 4, 5, 6]);
+        pcap.extend_from_slice(&[3, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 7, 8]);
+
+        let (_, mut reader) = PcapReader::new(&pcap[..]).unwrap();
+        assert_eq!(reader.position(), 24);
+        let (packet, record) = reader.next_with_record().unwrap().unwrap();
+        assert_eq!(packet.data, &[1, 2, 3, 4]);
+        assert_eq!(record, RecordInfo { offset: 24, ts_sec: 1, ts_usec: 2, incl_len: 6, orig_len: 9, truncated: true });
+        let (_, record) = reader.next_with_record().unwrap().unwrap();
+        assert_eq!(record, RecordInfo { offset: 46, ts_sec: 3, ts_usec: 4, incl_len: 2, orig_len: 2, truncated: false });
+        assert_eq!(reader.position(), pcap.len() as u64);
+        assert!(reader.next_with_record().unwrap().is_none());
+    }
+
+    #[test]
+    /// Makes sure recovery mode skips over corrupted data and reports what it skipped.
+    fn recovery() {
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        for i in 0..5u8 {
+            pcap.extend_from_slice(&[100 + i, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, i, i, i, i]);
+        }
+        // overwrite the end of the first and the start of the second packet with garbage
+        pcap[24 + 18..24 + 28].copy_from_slice(&[0xff; 10]);
+        // a partial record header at the end
+        pcap.extend_from_slice(&[1, 2, 3]);
+
+        let (_, mut reader) = PcapReader::new(&pcap[..]).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().data, &[0, 0, 0xff, 0xff]);
+        assert!(matches!(reader.next(), Err(PcapError::InvalidDate)));
+
+        let (_, mut reader) = PcapReader::with_options(&pcap[..], ReaderOptions::new().recover(true)).unwrap();
+        let mut packets = Vec::new();
+        while let Some((packet, record)) = reader.next_with_record().unwrap() {
+            packets.push((packet.data[0], record.offset));
+        }
+        assert_eq!(packets, [(0, 24), (2, 64), (3, 84), (4, 104)]);
+        assert_eq!(reader.skipped_ranges(), &[44..64, 124..127]);
+    }
+
+    #[test]
+    /// Makes sure large steps back in time are only accepted in recovery mode if they are within
+    /// the configured limit.
+    fn recovery_backwards() {
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        // one hour back in time after the first packet
+        for (i, &secs) in [7200u16, 3600, 3601].iter().enumerate() {
+            let [lo, hi] = secs.to_le_bytes();
+            pcap.extend_from_slice(&[lo, hi, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, i as u8, 0, 0, 0]);
+        }
+
+        let read = |options: ReaderOptions| {
+            let (_, mut reader) = PcapReader::with_options(&pcap[..], options.recover(true)).unwrap();
+            let mut packets = Vec::new();
+            while let Some(packet) = reader.next().unwrap() {
+                packets.push(packet.data[0]);
+            }
+            (packets, reader.skipped_ranges().to_vec())
+        };
+        let (packets, skipped) = read(ReaderOptions::new());
+        assert_eq!(packets, [0]);
+        assert_eq!(skipped, std::slice::from_ref(&(44..84)));
+        let hour = Duration::from_secs(3600);
+        assert_eq!(read(ReaderOptions::new().recover_max_backwards(hour)), (vec![0, 1, 2], vec![]));
+    }
+
+    #[test]
+    /// Makes sure follow mode keeps reading after the end of the data, and waits for partially
+    /// written records to be completed.
+    fn follow() {
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        pcap.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 1, 1, 1, 1]);
+        pcap.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 2, 2, 2, 2]);
+
+        let options = ReaderOptions::new().follow(true);
+        let (_, mut reader) = PcapReader::with_options(io::Cursor::new(pcap[..24].to_vec()), options).unwrap();
+        assert!(reader.next().unwrap().is_none());
+        for &(end, expected) in &[(30, None), (50, Some(1)), (60, None), (64, Some(2))] {
+            let data = reader.reader.uncompressed_mut().unwrap().get_mut();
+            let written = data.len();
+            data.extend_from_slice(&pcap[written..end]);
+            assert_eq!(reader.next().unwrap().map(|packet| packet.data[0]), expected);
+            assert!(reader.next().unwrap().is_none());
+        }
+        assert_eq!(reader.position(), 64);
+
+        // a record that is too large is only rejected once it is complete, but not buffered
+        let mut large = pcap[..24].to_vec();
+        large.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0, 12, 0, 0, 0, 12, 0, 0, 0]);
+        large.extend_from_slice(&[3; 12]);
+        large.extend_from_slice(&pcap[44..64]);
+        let (_, mut reader) = PcapReader::with_options(io::Cursor::new(large[..45].to_vec()), options.max_packet_size(8)).unwrap();
+        assert!(reader.next().unwrap().is_none());
+        assert_eq!(reader.lookahead.len(), 16);
+        reader.reader.uncompressed_mut().unwrap().get_mut().extend_from_slice(&large[45..60]);
+        assert!(matches!(reader.next(), Err(PcapError::InvalidRecordSize { packet: 0, offset: 24, incl_len: 12, orig_len: 12 })));
+        assert_eq!(reader.position(), 52);
+        assert!(reader.next().unwrap().is_none());
+        reader.reader.uncompressed_mut().unwrap().get_mut().extend_from_slice(&large[60..]);
+        assert_eq!(reader.next().unwrap().unwrap().data, [2; 4]);
+        assert_eq!(reader.position(), 72);
+    }
+
+    #[test]
+    /// Makes sure a non-zero UTC offset is applied on read and reverted on write.
+    fn utc_offset() {
+        // thiszone 3600, sigfigs 7, a packet at 4000 seconds
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x10, 0x0e, 0x00,
+                            0x00, 0x07, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        pcap.extend_from_slice(&[0xa0, 0x0f, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 42]);
+
+        let copy = |options: ReaderOptions, write_opts: Option<FileOptions>| {
+            let (opts, mut reader) = PcapReader::with_options(&pcap[..], options).unwrap();
+            assert_eq!((opts.utc_offset, opts.sigfigs), (3600, 7));
+            let mut writer = crate::write::PcapWriter::new(Vec::new(), write_opts.unwrap_or(opts)).unwrap();
+            writer.write(&reader.next().unwrap().unwrap()).unwrap();
+            writer.take_writer()
+        };
+
+        assert_eq!(copy(ReaderOptions::new(), None), pcap);
+
+        let (opts, _) = PcapSliceReader::new(&pcap[..]).unwrap();
+        let raw = copy(ReaderOptions::new().apply_utc_offset(false), Some(FileOptions { utc_offset: 0, ..opts }));
+        assert_eq!(raw[..8], pcap[..8]);
+        assert_eq!(raw[8..12], [0, 0, 0, 0]);
+        assert_eq!(raw[12..], pcap[12..]);
+    }
+
+    #[test]
+    /// Makes sure the FCS length is split from the link type, and preserved when appending.
+    fn fcs_length() {
+        // ethernet with a 4 byte FCS, and a reserved bit
+        let pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
+                        0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x24];
+        let (opts, _) = PcapSliceReader::new(&pcap[..]).unwrap();
+        assert_eq!(opts.linktype, crate::Linktype::ETHERNET);
+        assert_eq!(opts.fcs_length.map(crate::FcsLength::bytes), Some(4));
+        assert_eq!(opts.linktype_reserved, 0x0001_0000);
+
+        let writer = crate::write::PcapWriter::append(io::Cursor::new(pcap.clone())).unwrap();
+        assert_eq!(writer.get_options(), opts);
+        assert_eq!(writer.take_writer().into_inner(), pcap);
+
+        let writer = crate::write::PcapWriter::new(Vec::new(), opts).unwrap();
+        assert_eq!(writer.take_writer(), pcap);
+    }
+
+    #[test]
+    /// Reads and writes captures with the legacy magic numbers.
+    fn legacy_variants() {
+        // modified format, big endian, two packets with extended records
+        let mut pcap = vec![0xa1u8, 0xb2, 0xcd, 0x34, 0x00, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00,
+                            0x01];
+        pcap.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2,
+                                 0, 0, 0, 3, 0x08, 0x00, 4, 0, 0xaa, 0xbb]);
+        pcap.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 1,
+                                 0xff, 0xff, 0xff, 0xff, 0x86, 0xdd, 0, 0, 0xcc]);
+
+        let (opts, reader) = PcapSliceReader::new(&pcap[..]).unwrap();
+        assert_eq!(opts.variant, crate::PcapVariant::Modified);
+        assert!(!opts.high_res_timestamps);
+        let packets: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].data, [0xaa, 0xbb]);
+        assert_eq!(packets[0].extended, Some(ExtendedRecord { ifindex: 3, protocol: 0x0800, pkt_type: 4 }));
+        assert_eq!(packets[1].extended, Some(ExtendedRecord { ifindex: -1, protocol: 0x86dd, pkt_type: 0 }));
+
+        let (_, mut reader) = PcapReader::new(&pcap[..]).unwrap();
+        let mut writer = crate::write::PcapWriter::new(Vec::new(), opts).unwrap();
+        while let Some(packet) = reader.next().unwrap() {
+            writer.write(&packet).unwrap();
+        }
+        assert_eq!(writer.take_writer(), pcap);
+
+        // IXIA formats have nanosecond timestamps, but otherwise standard records
+        for &magic in &[0x1c00_01acu32, 0x1c00_01ab] {
+            let mut ixia = magic.to_be_bytes().to_vec();
+            ixia.extend_from_slice(&pcap[4..24]);
+            ixia.extend_from_slice(&pcap[24..40]);
+            ixia.extend_from_slice(&[0xaa, 0xbb]);
+            let (opts, reader) = PcapSliceReader::new(&ixia[..]).unwrap();
+            assert!(opts.high_res_timestamps);
+            assert!(!opts.variant.extended_records());
+            let packets: Vec<_> = reader.map(Result::unwrap).collect();
+            assert_eq!(packets.len(), 1);
+            assert_eq!(packets[0].extended, None);
+
+            let mut writer = crate::write::PcapWriter::new(Vec::new(), opts).unwrap();
+            writer.write(&packets[0]).unwrap();
+            assert_eq!(writer.take_writer(), ixia);
+        }
+
+        // Nokia format, standard magic, but 4 more bytes in every record header
+        let mut nokia = vec![0xa1u8, 0xb2, 0xc3, 0xd4];
+        nokia.extend_from_slice(&pcap[4..40]);
+        nokia.extend_from_slice(&[0, 0, 0, 0, 0xaa, 0xbb]);
+        let (opts, reader) = PcapSliceReader::with_options(&nokia[..], ReaderOptions::new().nokia(true)).unwrap();
+        assert_eq!(opts.variant, crate::PcapVariant::Nokia);
+        let packets: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, [0xaa, 0xbb]);
+        assert_eq!(packets[0].extended, None);
+        let (_, mut reader) = PcapReader::with_options(&nokia[..], ReaderOptions::new().nokia(true)).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().data, [0xaa, 0xbb]);
+        assert!(reader.next().unwrap().is_none());
+
+        let mut writer = crate::write::PcapWriter::new(Vec::new(), opts).unwrap();
+        writer.write(&packets[0]).unwrap();
+        assert_eq!(writer.take_writer(), nokia);
+
+        // without the option, the additional bytes are read as part of the packet
+        let (standard, mut reader) = PcapSliceReader::new(&nokia[..]).unwrap();
+        assert_eq!(standard.variant, crate::PcapVariant::Standard);
+        assert_eq!(reader.next().unwrap().unwrap().data, [0, 0]);
+
+        let invalid = FileOptions { high_res_timestamps: true, ..opts };
+        assert!(matches!(crate::write::PcapWriter::new(Vec::new(), invalid), Err(PcapError::InvalidFileHeader)));
+    }
+
+    #[test]
+    /// Makes sure the iterator adapters return all packets.
+    fn iterators() {
+        let mut pcap = vec![0xd4u8, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00,
+                            0x00];
+        for i in 0..6u8 {
+            pcap.extend_from_slice(&[i, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, i]);
+        }
+
+        let (_, reader) = PcapReader::new(&pcap[..]).unwrap();
+        let even: Vec<u8> = reader.into_owned_iter()
+            .map(Result::unwrap)
+            .filter(|p| p.data[0] % 2 == 0)
+            .map(|p| p.data[0])
+            .collect();
+        assert_eq!(even, [0, 2, 4]);
+
+        let (_, mut reader) = PcapReader::new(&pcap[..]).unwrap();
+        let mut count = 0;
+        reader.for_each(|_| count += 1).unwrap();
+        assert_eq!(count, 6);
+
+        let (_, mut reader) = PcapReader::new(&pcap[..]).unwrap();
+        let sum = reader.try_fold(0, |acc, p| Ok::<_, PcapError>(acc + p.data[0])).unwrap();
+        assert_eq!(sum, 15);
+
+        let (_, mut reader) = PcapReader::new(&pcap[..]).unwrap();
+        let res = reader.try_fold((), |_, p| if p.data[0] < 3 { Ok(()) } else { Err(PcapError::InvalidDate) });
+        assert!(matches!(res, Err(PcapError::InvalidDate)));
+        assert_eq!(reader.next().unwrap().unwrap().data, &[4]);
+    }
 }
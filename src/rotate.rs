@@ -0,0 +1,452 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::def;
+use super::write::{encode_file_header, PcapWriter, WriteOptions};
+use super::CapturedPacket;
+use super::PcapError;
+use super::{Resolution, Rounding, Timestamp};
+
+
+/// The `RotateOptions` struct decides when a `RotatingPcapWriter` starts a new file, how the
+/// files are named, and how many of them are kept.
+///
+/// ```
+/// use std::time::Duration;
+/// use rpcap::rotate::RotateOptions;
+///
+/// // like `tcpdump -C 100 -W 10`, and a new file at least every hour
+/// let options = RotateOptions::new("capture-%Y%m%d-%H%M%S-%03n.pcap")
+///     .max_bytes(100_000_000)
+///     .max_duration(Duration::from_secs(3600))
+///     .max_files(10);
+/// ```
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct RotateOptions {
+    template: String,
+    max_bytes: Option<u64>,
+    max_duration: Option<Duration>,
+    max_packets: Option<u64>,
+    max_files: Option<usize>,
+}
+
+impl RotateOptions {
+    /// Creates options that write all packets to a single file, whose name is generated from
+    /// `template`. The template can contain these placeholders:
+    ///
+    /// * `%n`: the number of the file, starting at 0. With a width, as in `%03n`, the number is
+    ///   padded with zeroes.
+    /// * `%Y`, `%m`, `%d`, `%H`, `%M` and `%S`: the year, month, day, hour, minute and second of
+    ///   the first packet in the file, in UTC.
+    /// * `%s`: the time of the first packet in the file, in seconds since the Unix epoch.
+    /// * `%%`: a literal `%`.
+    ///
+    /// The generated names have to be unique. Unless files are only rotated by duration, and the
+    /// time in the name is precise enough, the template should contain `%n`. Existing files are
+    /// never overwritten: starting a file whose name is taken fails with
+    /// `io::ErrorKind::AlreadyExists`.
+    pub fn new<S: Into<String>>(template: S) -> Self {
+        RotateOptions {
+            template: template.into(),
+            max_bytes: None,
+            max_duration: None,
+            max_packets: None,
+            max_files: None,
+        }
+    }
+
+    /// Sets the maximum size of a file in bytes, including the file header. A packet that would
+    /// make the file larger than this is written to a new file instead. Only a single packet that
+    /// is larger than the limit on its own exceeds it.
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets the time span that a file covers. A packet whose timestamp is this long or longer
+    /// after the first packet in the file is written to a new file.
+    pub fn max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Sets the maximum number of packets in a file.
+    pub fn max_packets(mut self, max_packets: u64) -> Self {
+        self.max_packets = Some(max_packets);
+        self
+    }
+
+    /// Keeps only the newest `max_files` files, deleting the oldest one before a new file is
+    /// started, so the files form a ring buffer.
+    pub fn max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    /// Checks that the limits are usable and the template is valid.
+    fn validate(&self) -> io::Result<()> {
+        if self.max_bytes == Some(0) || self.max_duration == Some(Duration::ZERO) ||
+                self.max_packets == Some(0) || self.max_files == Some(0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "rotation limits must not be zero"));
+        }
+        file_name(&self.template, 0, Timestamp::default()).map(drop)
+    }
+}
+
+/// The `RotatingPcapWriter` struct writes packets to a series of capture files, like
+/// `tcpdump -C/-G/-W` or `dumpcap -b`. Each file is a complete capture with its own file header.
+///
+/// A file is only created when the first packet for it is written, so that its name can contain
+/// the time of that packet.
+pub struct RotatingPcapWriter {
+    options: RotateOptions,
+    opts: WriteOptions,
+    rounding: Rounding,
+    current: Option<CurrentFile>,
+    files: Vec<PathBuf>,
+    next_index: u64,
+    lossy_timestamps: u64,
+}
+
+/// The file that `RotatingPcapWriter` is currently writing to.
+struct CurrentFile {
+    writer: PcapWriter<BufWriter<File>>,
+    start: Timestamp,
+    bytes: u64,
+    packets: u64,
+}
+
+impl RotatingPcapWriter {
+    /// Create a new `RotatingPcapWriter` that writes files with the file options `opts`, rotated
+    /// according to `options`.
+    pub fn new(opts: WriteOptions, options: RotateOptions) -> Result<Self, PcapError> {
+        encode_file_header(opts)?;
+        options.validate()?;
+
+        Ok(RotatingPcapWriter {
+            options,
+            opts,
+            rounding: Rounding::default(),
+            current: None,
+            files: Vec::new(),
+            next_index: 0,
+            lossy_timestamps: 0,
+        })
+    }
+
+    /// Sets how timestamps are rounded, like `PcapWriter::set_rounding`.
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+        if let Some(ref mut file) = self.current {
+            file.writer.set_rounding(rounding);
+        }
+    }
+
+    /// The number of packets written so far whose timestamp had to be rounded, in all files.
+    pub fn lossy_timestamps(&self) -> u64 {
+        self.lossy_timestamps + self.current.as_ref().map_or(0, |file| file.writer.lossy_timestamps())
+    }
+
+    /// Write a packet to the current file, starting a new file first if it would exceed one of
+    /// the limits.
+    ///
+    /// If the oldest file has to be deleted to start a new file and that fails, the error is
+    /// returned and neither the new file nor the packet are written. The oldest file is still
+    /// returned by `files`, and deleting it is tried again with the next packet.
+    pub fn write(&mut self, packet: &CapturedPacket) -> Result<(), PcapError> {
+        let time = packet.timestamp().ok_or(PcapError::InvalidDate)?;
+        let record_len = (self.opts.variant.record_header_len() + usize::min(packet.data.len(), self.opts.snaplen)) as u64;
+
+        if self.current.as_ref().is_some_and(|file| self.must_rotate(file, time, record_len)) {
+            self.rotate()?;
+        }
+        if self.current.is_none() {
+            self.start_file(time)?;
+        }
+
+        let file = self.current.as_mut().unwrap();
+        file.writer.write(packet)?;
+        file.bytes += record_len;
+        file.packets += 1;
+        Ok(())
+    }
+
+    /// Completes the current file, so that the next packet is written to a new file.
+    pub fn rotate(&mut self) -> Result<(), PcapError> {
+        if let Some(file) = self.current.take() {
+            self.lossy_timestamps += file.writer.lossy_timestamps();
+            file.writer.take_writer().into_inner().map_err(io::IntoInnerError::into_error)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the current file.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        match self.current {
+            Some(ref mut file) => file.writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Completes the current file, and returns the paths of all files that were written and not
+    /// deleted again, from oldest to newest.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>, PcapError> {
+        self.rotate()?;
+        Ok(self.files)
+    }
+
+    /// The paths of all files that were written and not deleted again, from oldest to newest.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The path of the file that is currently being written, if any.
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().and(self.files.last()).map(PathBuf::as_path)
+    }
+
+    /// The options used for the files written by this `RotatingPcapWriter`.
+    pub fn get_options(&self) -> WriteOptions {
+        self.opts
+    }
+
+    /// Checks whether writing a record of `record_len` bytes at `time` to `file` would exceed
+    /// one of the limits.
+    fn must_rotate(&self, file: &CurrentFile, time: Timestamp, record_len: u64) -> bool {
+        if file.packets == 0 {
+            return false;
+        }
+        let too_many = self.options.max_packets.is_some_and(|max| file.packets >= max);
+        let too_large = self.options.max_bytes.is_some_and(|max| file.bytes + record_len > max);
+        let too_late = self.options.max_duration.is_some_and(|max| {
+            let nanos = |ts: Timestamp| ts.to_units(Resolution::NANOSECONDS, Rounding::Truncate).unwrap().units;
+            nanos(time) - nanos(file.start) >= max.as_nanos() as i128
+        });
+        too_many || too_large || too_late
+    }
+
+    /// Deletes the oldest file if there are too many, and creates the next file, named after the
+    /// time of its first packet.
+    ///
+    /// If the oldest file can't be deleted, it is kept in `files` and no new file is created.
+    fn start_file(&mut self, time: Timestamp) -> Result<(), PcapError> {
+        let path = file_name(&self.options.template, self.next_index, time)?;
+        if self.files.contains(&path) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                      "file name template generated the same name twice").into());
+        }
+
+        if self.options.max_files.is_some_and(|max| self.files.len() >= max) {
+            if let Err(e) = fs::remove_file(&self.files[0]) {
+                // the file may have been removed by someone else already
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+            self.files.remove(0);
+        }
+
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        let mut writer = PcapWriter::new(BufWriter::new(file), self.opts)?;
+        writer.set_rounding(self.rounding);
+        self.current = Some(CurrentFile { writer, start: time, bytes: def::PcapFileHeader::LEN, packets: 0 });
+        self.files.push(path);
+        self.next_index += 1;
+        Ok(())
+    }
+}
+
+/// Generates the name of a file from the `template`, see `RotateOptions::new`.
+fn file_name(template: &str, index: u64, time: Timestamp) -> io::Result<PathBuf> {
+    let (year, month, day) = civil_from_days(time.secs().div_euclid(86400));
+    let secs_of_day = time.secs().rem_euclid(86400);
+
+    let mut name = String::with_capacity(template.len() + 16);
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            name.push(c);
+            continue;
+        }
+        let mut width = 0;
+        let spec = loop {
+            match chars.next() {
+                Some(digit @ '0'..='9') if width < 20 => width = width * 10 + digit as usize - '0' as usize,
+                spec => break spec,
+            }
+        };
+        let placeholder = match spec {
+            Some('n') => format!("{:0width$}", index, width = width),
+            _ if width > 0 => String::new(),
+            Some('Y') => format!("{:04}", year),
+            Some('m') => format!("{:02}", month),
+            Some('d') => format!("{:02}", day),
+            Some('H') => format!("{:02}", secs_of_day / 3600),
+            Some('M') => format!("{:02}", secs_of_day / 60 % 60),
+            Some('S') => format!("{:02}", secs_of_day % 60),
+            Some('s') => time.secs().to_string(),
+            Some('%') => "%".to_string(),
+            _ => String::new(),
+        };
+        if placeholder.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid placeholder in file name template"));
+        }
+        name.push_str(&placeholder);
+    }
+    Ok(PathBuf::from(name))
+}
+
+/// Converts a number of days since 1970-01-01 to a date in the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use std::process;
+
+    use super::*;
+    use super::super::read::PcapReader;
+    use super::super::test::write_options;
+
+    /// Writes packets with a size of 4 bytes, one every second, and returns the first byte of the
+    /// packets in each file that remains.
+    fn rotate(name: &str, options: impl Fn(RotateOptions) -> RotateOptions) -> Vec<Vec<u8>> {
+        let template = std::env::temp_dir().join(format!("rpcap-rotate-{}-{}-%n.pcap", name, process::id()));
+        let options = options(RotateOptions::new(template.to_str().unwrap()));
+        let mut writer = RotatingPcapWriter::new(write_options(), options).unwrap();
+        for i in 0..10u8 {
+            let time = Timestamp::new(1_000_000_000 + i64::from(i), 0).unwrap();
+            writer.write(&CapturedPacket { time: time.to_time().unwrap(), data: &[i; 4], orig_len: 4, extended: None }).unwrap();
+        }
+        writer.finish().unwrap().iter().map(|path| {
+            let (_, reader) = PcapReader::new(File::open(path).unwrap()).unwrap();
+            let packets = reader.into_owned_iter().map(|packet| packet.unwrap().data[0]).collect();
+            fs::remove_file(path).unwrap();
+            packets
+        }).collect()
+    }
+
+    #[test]
+    /// Makes sure files are rotated exactly at the limits.
+    fn limits() {
+        assert_eq!(rotate("none", |options| options), [(0..10).collect::<Vec<_>>()]);
+        assert_eq!(rotate("packets", |options| options.max_packets(4)), [vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+        // the file header and three records fit exactly
+        assert_eq!(rotate("bytes", |options| options.max_bytes(24 + 3 * 20)), [vec![0, 1, 2], vec![3, 4, 5], vec![6, 7, 8], vec![9]]);
+        assert_eq!(rotate("small", |options| options.max_bytes(1)), (0..10).map(|i| vec![i]).collect::<Vec<_>>());
+        let five_secs = Duration::from_secs(5);
+        assert_eq!(rotate("duration", |options| options.max_duration(five_secs)), [vec![0, 1, 2, 3, 4], vec![5, 6, 7, 8, 9]]);
+        assert_eq!(rotate("combined", |options| options.max_duration(five_secs).max_packets(3)), [vec![0, 1, 2], vec![3, 4, 5], vec![6, 7, 8], vec![9]]);
+    }
+
+    #[test]
+    /// Makes sure only the newest files are kept as a ring buffer.
+    fn ring_buffer() {
+        assert_eq!(rotate("ring", |options| options.max_packets(3).max_files(2)), [vec![6, 7, 8], vec![9]]);
+        let template = std::env::temp_dir().join(format!("rpcap-rotate-ring-{}-%n.pcap", process::id()));
+        for i in 0..2 {
+            assert!(!Path::new(&template.to_str().unwrap().replace("%n", &i.to_string())).exists());
+        }
+    }
+
+    #[test]
+    /// Makes sure no new file is started if the oldest file can't be deleted.
+    fn failed_delete() {
+        let template = std::env::temp_dir().join(format!("rpcap-rotate-delete-{}-%n.pcap", process::id()));
+        let options = RotateOptions::new(template.to_str().unwrap()).max_packets(1).max_files(1);
+        let mut writer = RotatingPcapWriter::new(write_options(), options).unwrap();
+        let packet = |i: u8| {
+            let time = Timestamp::new(1_000_000_000 + i64::from(i), 0).unwrap();
+            CapturedPacket { time: time.to_time().unwrap(), data: &[], orig_len: i.into(), extended: None }
+        };
+        writer.write(&packet(0)).unwrap();
+
+        // a non-empty directory can't be removed with `remove_file`
+        let oldest = writer.files()[0].clone();
+        writer.rotate().unwrap();
+        fs::remove_file(&oldest).unwrap();
+        fs::create_dir(&oldest).unwrap();
+        fs::write(oldest.join("file"), b"").unwrap();
+        assert!(writer.write(&packet(1)).is_err());
+        assert_eq!(writer.files(), [oldest.as_path()]);
+        assert_eq!(writer.current_path(), None);
+
+        fs::remove_dir_all(&oldest).unwrap();
+        writer.write(&packet(1)).unwrap();
+        let files = writer.finish().unwrap();
+        assert_eq!(files.len(), 1);
+        let (_, reader) = PcapReader::new(File::open(&files[0]).unwrap()).unwrap();
+        let packets: Vec<_> = reader.into_owned_iter().map(|packet| packet.unwrap().orig_len).collect();
+        assert_eq!(packets, [1]);
+        fs::remove_file(&files[0]).unwrap();
+    }
+
+    #[test]
+    /// Makes sure the placeholders in file name templates are replaced, and invalid ones rejected.
+    fn templates() {
+        let time = Timestamp::new(1_000_000_000, 0).unwrap();
+        assert_eq!(file_name("%Y-%m-%d %H:%M:%S %s %n %05n 100%%", 42, time).unwrap(),
+                   Path::new("2001-09-09 01:46:40 1000000000 42 00042 100%"));
+        assert_eq!(file_name("%Y%m%d-%H%M%S", 0, Timestamp::new(-1, 0).unwrap()).unwrap(), Path::new("19691231-235959"));
+        assert_eq!(file_name("%Y-%m-%d", 0, Timestamp::new(951_782_400, 0).unwrap()).unwrap(), Path::new("2000-02-29"));
+        for template in &["%", "%x", "%2Y", "capture-%"] {
+            assert!(file_name(template, 0, time).is_err());
+        }
+
+        assert!(RotatingPcapWriter::new(write_options(), RotateOptions::new("%q")).is_err());
+        assert!(RotatingPcapWriter::new(write_options(), RotateOptions::new("%n").max_packets(0)).is_err());
+
+        // without `%n`, a second file in the same second would overwrite the first one
+        let template = std::env::temp_dir().join(format!("rpcap-rotate-same-{}-%s.pcap", process::id()));
+        let options = RotateOptions::new(template.to_str().unwrap()).max_packets(1);
+        let mut writer = RotatingPcapWriter::new(write_options(), options).unwrap();
+        let packet = CapturedPacket { time: time.to_time().unwrap(), data: &[], orig_len: 0, extended: None };
+        writer.write(&packet).unwrap();
+        assert!(writer.write(&packet).is_err());
+        assert_eq!(writer.current_path(), None);
+        fs::remove_file(&writer.files()[0]).unwrap();
+
+        // names that repeat later, like the hour of the day, don't overwrite older files
+        let template = std::env::temp_dir().join(format!("rpcap-rotate-repeat-{}-%S.pcap", process::id()));
+        let options = RotateOptions::new(template.to_str().unwrap()).max_packets(1).max_files(3);
+        let mut writer = RotatingPcapWriter::new(write_options(), options).unwrap();
+        for &secs in &[0, 1] {
+            let time = Timestamp::new(1_000_000_000 + secs, 0).unwrap().to_time().unwrap();
+            writer.write(&CapturedPacket { time, data: &[secs as u8; 4], orig_len: 4, extended: None }).unwrap();
+        }
+        let time = Timestamp::new(1_000_000_060, 0).unwrap().to_time().unwrap();
+        let err = writer.write(&CapturedPacket { time, data: &[2; 4], orig_len: 4, extended: None }).unwrap_err();
+        assert!(matches!(err, PcapError::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+        let files = writer.finish().unwrap();
+        assert_eq!(files.len(), 2);
+        for (i, path) in files.iter().enumerate() {
+            let (_, reader) = PcapReader::new(File::open(path).unwrap()).unwrap();
+            let packets: Vec<_> = reader.into_owned_iter().map(|packet| packet.unwrap().data[0]).collect();
+            assert_eq!(packets, [i as u8]);
+            fs::remove_file(path).unwrap();
+        }
+
+        // neither do files that existed before
+        let path = std::env::temp_dir().join(format!("rpcap-rotate-existing-{}.pcap", process::id()));
+        fs::write(&path, b"old").unwrap();
+        let mut writer = RotatingPcapWriter::new(write_options(), RotateOptions::new(path.to_str().unwrap())).unwrap();
+        assert!(writer.write(&packet).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,338 @@
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Time;
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+/// The resolution of timestamps in a packet capture.
+///
+/// Files of the traditional format use microseconds or nanoseconds. Interfaces in pcapng files
+/// can use any negative power of 10 or 2, as specified by their `if_tsresol` option.
+#[derive(Copy,Clone,PartialEq,Eq,Hash,Debug)]
+pub enum Resolution {
+    /// Units of 10<sup>-n</sup> seconds.
+    Decimal(u8),
+    /// Units of 2<sup>-n</sup> seconds.
+    Binary(u8),
+}
+impl Resolution {
+    /// Microseconds, the default resolution of both file formats.
+    pub const MICROSECONDS: Resolution = Resolution::Decimal(6);
+    /// Nanoseconds.
+    pub const NANOSECONDS: Resolution = Resolution::Decimal(9);
+
+    /// Decodes the value of a pcapng `if_tsresol` option: if the most significant bit is clear,
+    /// the remaining bits are a negative power of 10, otherwise they are a negative power of 2.
+    pub fn from_tsresol(tsresol: u8) -> Resolution {
+        if tsresol & 0x80 == 0 {
+            Resolution::Decimal(tsresol)
+        } else {
+            Resolution::Binary(tsresol & 0x7f)
+        }
+    }
+
+    /// Encodes the resolution as the value of a pcapng `if_tsresol` option. Returns `None` if the
+    /// exponent is too large.
+    pub fn tsresol(self) -> Option<u8> {
+        match self {
+            Resolution::Decimal(exp) if exp < 0x80 => Some(exp),
+            Resolution::Binary(exp) if exp < 0x80 => Some(0x80 | exp),
+            _ => None,
+        }
+    }
+
+    /// The number of units per second. Returns `None` if it doesn't fit into a `u64`, which also
+    /// means that timestamps of this resolution can't be stored in a file.
+    pub fn units_per_sec(self) -> Option<u64> {
+        match self {
+            Resolution::Decimal(exp) => 10u64.checked_pow(u32::from(exp)),
+            Resolution::Binary(exp) => 1u64.checked_shl(u32::from(exp)),
+        }
+    }
+}
+impl Default for Resolution {
+    fn default() -> Resolution {
+        Resolution::MICROSECONDS
+    }
+}
+
+/// How a timestamp is rounded when it is converted to a resolution that can't represent it
+/// exactly.
+#[derive(Copy,Clone,PartialEq,Eq,Hash,Debug,Default)]
+pub enum Rounding {
+    /// Round to the nearest unit, and up if the timestamp is exactly halfway between two units.
+    #[default]
+    Round,
+    /// Round down, towards the past.
+    Truncate,
+    /// Round up, towards the future.
+    Ceil,
+}
+
+/// A timestamp converted to a count of units of some resolution, see `Timestamp::to_units`.
+#[derive(Copy,Clone,PartialEq,Eq,Hash,Debug)]
+pub struct ConvertedTimestamp {
+    /// The number of units since the UNIX epoch.
+    pub units: i128,
+    /// `true` if the timestamp had to be rounded, i.e. converting back yields a different
+    /// timestamp.
+    pub lossy: bool,
+}
+
+/// A point in time, stored as seconds and nanoseconds relative to the UNIX epoch.
+///
+/// In contrast to the platform's time types, it can represent every timestamp that can be stored
+/// in the supported file formats, including those before 1970 and after 2106. Timestamps are
+/// converted to the `Resolution` of a file with `to_units` and back with `from_units`.
+///
+/// The resolution is not part of the timestamp: it is a property of a file or pcapng interface,
+/// see `FileOptions::resolution` and `InterfaceOptions::ts_resolution`, and is passed to the
+/// conversions instead. That way, the same point in time compares equal no matter which file it
+/// was read from.
+#[derive(Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash,Debug,Default)]
+pub struct Timestamp {
+    secs: i64,
+    nanos: u32,
+}
+
+impl Timestamp {
+    /// The UNIX epoch, 1970-01-01 00:00:00 UTC.
+    pub const UNIX_EPOCH: Timestamp = Timestamp { secs: 0, nanos: 0 };
+
+    /// Creates a timestamp `secs` seconds and `nanos` nanoseconds after the UNIX epoch. For times
+    /// before the epoch, `secs` is negative and `nanos` still counts forward.
+    ///
+    /// Returns `None` if `nanos` is not less than one second.
+    pub fn new(secs: i64, nanos: u32) -> Option<Timestamp> {
+        if nanos < NANOS_PER_SEC {
+            Some(Timestamp { secs, nanos })
+        } else {
+            None
+        }
+    }
+
+    /// The whole seconds since the UNIX epoch, rounded down.
+    pub fn secs(self) -> i64 {
+        self.secs
+    }
+
+    /// The nanoseconds after `secs`.
+    pub fn subsec_nanos(self) -> u32 {
+        self.nanos
+    }
+
+    /// Creates a timestamp from a count of `units` of `resolution` since the UNIX epoch. Parts of
+    /// a nanosecond are truncated.
+    ///
+    /// Returns `None` if the resolution can't be used in files, or if the result is out of range.
+    pub fn from_units(units: i128, resolution: Resolution) -> Option<Timestamp> {
+        let units_per_sec = i128::from(resolution.units_per_sec()?);
+        let secs = i64::try_from(units.div_euclid(units_per_sec)).ok()?;
+        let frac = units.rem_euclid(units_per_sec);
+        let nanos = frac * i128::from(NANOS_PER_SEC) / units_per_sec;
+        Some(Timestamp { secs, nanos: nanos as u32 })
+    }
+
+    /// Converts this timestamp to a count of units of `resolution` since the UNIX epoch. If the
+    /// resolution can't represent the timestamp exactly, it is rounded as specified by `rounding`
+    /// and the result is marked as lossy.
+    ///
+    /// Returns `None` if the resolution can't be used in files, or if the result is out of range.
+    pub fn to_units(self, resolution: Resolution, rounding: Rounding) -> Option<ConvertedTimestamp> {
+        let units_per_sec = i128::from(resolution.units_per_sec()?);
+        let nanos_per_sec = i128::from(NANOS_PER_SEC);
+        let scaled = i128::from(self.nanos) * units_per_sec;
+        let (frac, rem) = (scaled / nanos_per_sec, scaled % nanos_per_sec);
+        let round_up = match rounding {
+            Rounding::Round => rem * 2 >= nanos_per_sec,
+            Rounding::Truncate => false,
+            Rounding::Ceil => rem != 0,
+        };
+        let units = i128::from(self.secs).checked_mul(units_per_sec)?
+            .checked_add(frac + i128::from(round_up))?;
+        Some(ConvertedTimestamp { units, lossy: rem != 0 })
+    }
+
+    /// Adds `secs` seconds to this timestamp. Returns `None` on overflow.
+    pub fn checked_add_secs(self, secs: i64) -> Option<Timestamp> {
+        Some(Timestamp { secs: self.secs.checked_add(secs)?, nanos: self.nanos })
+    }
+
+    /// Subtracts `secs` seconds from this timestamp. Returns `None` on overflow.
+    pub fn checked_sub_secs(self, secs: i64) -> Option<Timestamp> {
+        Some(Timestamp { secs: self.secs.checked_sub(secs)?, nanos: self.nanos })
+    }
+
+    /// Converts a `SystemTime` to a timestamp. Returns `None` if it is out of range.
+    pub fn from_system_time(time: SystemTime) -> Option<Timestamp> {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Timestamp::new(i64::try_from(after.as_secs()).ok()?, after.subsec_nanos()),
+            Err(err) => {
+                let before = err.duration();
+                let secs = -i64::try_from(before.as_secs()).ok()?;
+                match before.subsec_nanos() {
+                    0 => Timestamp::new(secs, 0),
+                    nanos => Timestamp::new(secs.checked_sub(1)?, NANOS_PER_SEC - nanos),
+                }
+            },
+        }
+    }
+
+    /// Converts this timestamp to a `SystemTime`. Returns `None` if the platform can't represent
+    /// it.
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        let nanos = Duration::new(0, self.nanos);
+        if self.secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::new(self.secs as u64, 0))?.checked_add(nanos)
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::new(self.secs.unsigned_abs(), 0))?.checked_add(nanos)
+        }
+    }
+
+    /// Converts a `Time` to a timestamp. Returns `None` if it is out of range.
+    #[cfg(not(feature = "time"))]
+    pub(crate) fn from_time(time: Time) -> Option<Timestamp> {
+        Timestamp::from_system_time(time)
+    }
+    /// Converts a `Time` to a timestamp. Returns `None` if it is out of range.
+    #[cfg(feature = "time")]
+    pub(crate) fn from_time(time: Time) -> Option<Timestamp> {
+        Timestamp::new(time.sec, u32::try_from(time.nsec).ok()?)
+    }
+
+    /// Converts this timestamp to a `Time`. Returns `None` if the platform can't represent it.
+    #[cfg(not(feature = "time"))]
+    pub(crate) fn to_time(self) -> Option<Time> {
+        self.to_system_time()
+    }
+    /// Converts this timestamp to a `Time`. Returns `None` if the platform can't represent it.
+    #[cfg(feature = "time")]
+    pub(crate) fn to_time(self) -> Option<Time> {
+        Some(Time::new(self.secs, self.nanos as i32))
+    }
+}
+
+impl TryFrom<SystemTime> for Timestamp {
+    type Error = ();
+
+    fn try_from(time: SystemTime) -> Result<Self, Self::Error> {
+        Timestamp::from_system_time(time).ok_or(())
+    }
+}
+impl TryFrom<Timestamp> for SystemTime {
+    type Error = ();
+
+    fn try_from(ts: Timestamp) -> Result<Self, Self::Error> {
+        ts.to_system_time().ok_or(())
+    }
+}
+
+#[cfg(feature = "time03")]
+impl From<time03::OffsetDateTime> for Timestamp {
+    fn from(time: time03::OffsetDateTime) -> Timestamp {
+        Timestamp { secs: time.unix_timestamp(), nanos: time.nanosecond() }
+    }
+}
+#[cfg(feature = "time03")]
+impl TryFrom<Timestamp> for time03::OffsetDateTime {
+    type Error = ();
+
+    /// Converts the timestamp to a date in UTC. Fails for years beyond 9999.
+    fn try_from(ts: Timestamp) -> Result<Self, Self::Error> {
+        let nanos = i128::from(ts.secs) * i128::from(NANOS_PER_SEC) + i128::from(ts.nanos);
+        time03::OffsetDateTime::from_unix_timestamp_nanos(nanos).or(Err(()))
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<chrono::DateTime<chrono::Utc>> for Timestamp {
+    /// Converts a date to a timestamp. Leap seconds are counted as the following second.
+    fn from(time: chrono::DateTime<chrono::Utc>) -> Timestamp {
+        let secs = time.timestamp();
+        match time.timestamp_subsec_nanos() {
+            nanos if nanos >= NANOS_PER_SEC => Timestamp { secs: secs + 1, nanos: nanos - NANOS_PER_SEC },
+            nanos => Timestamp { secs, nanos },
+        }
+    }
+}
+#[cfg(feature = "chrono")]
+impl TryFrom<Timestamp> for chrono::DateTime<chrono::Utc> {
+    type Error = ();
+
+    /// Converts the timestamp to a date. Fails if the date is out of the range of `chrono`.
+    fn try_from(ts: Timestamp) -> Result<Self, Self::Error> {
+        chrono::DateTime::from_timestamp(ts.secs, ts.nanos).ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+    use std::time::SystemTime;
+
+    use super::{ConvertedTimestamp, Resolution, Rounding, Timestamp};
+
+    #[test]
+    fn units() {
+        let ms = Resolution::Decimal(3);
+        let ts = Timestamp::new(-2, 250_000_000).unwrap();
+        assert_eq!(ts.to_units(ms, Rounding::Round), Some(ConvertedTimestamp { units: -1750, lossy: false }));
+        assert_eq!(Timestamp::from_units(-1750, ms), Some(ts));
+        assert_eq!(Timestamp::from_units(-1, Resolution::Decimal(0)), Timestamp::new(-1, 0));
+        assert_eq!(Timestamp::from_units(3, Resolution::Binary(2)), Timestamp::new(0, 750_000_000));
+
+        // rounding, with carry into the seconds
+        let ts = Timestamp::new(5, 999_999_500).unwrap();
+        let units = |rounding| ts.to_units(Resolution::MICROSECONDS, rounding).unwrap();
+        assert_eq!(units(Rounding::Round), ConvertedTimestamp { units: 6_000_000, lossy: true });
+        assert_eq!(units(Rounding::Truncate), ConvertedTimestamp { units: 5_999_999, lossy: true });
+        assert_eq!(units(Rounding::Ceil), ConvertedTimestamp { units: 6_000_000, lossy: true });
+        let ts = Timestamp::new(-6, 400).unwrap();
+        let units = |rounding| ts.to_units(ms, rounding).unwrap().units;
+        assert_eq!((units(Rounding::Round), units(Rounding::Truncate), units(Rounding::Ceil)), (-6000, -6000, -5999));
+
+        let far = Timestamp::new(1 << 40, 0).unwrap();
+        let ns = far.to_units(Resolution::NANOSECONDS, Rounding::Truncate).unwrap();
+        assert!(!ns.lossy);
+        assert_eq!(Timestamp::from_units(ns.units, Resolution::NANOSECONDS), Some(far));
+        assert_eq!(Timestamp::new(i64::MAX, 0).unwrap().checked_add_secs(1), None);
+        assert_eq!(Timestamp::new(0, 1_000_000_000), None);
+        assert_eq!(Timestamp::from_units(1, Resolution::Decimal(20)), None);
+    }
+
+    #[test]
+    fn resolutions() {
+        for tsresol in 0..=255 {
+            let resolution = Resolution::from_tsresol(tsresol);
+            assert_eq!(resolution.tsresol(), Some(tsresol));
+        }
+        assert_eq!(Resolution::from_tsresol(6), Resolution::default());
+        assert_eq!(Resolution::from_tsresol(0x80 | 10).units_per_sec(), Some(1024));
+        assert_eq!(Resolution::Decimal(19).units_per_sec(), Some(10_000_000_000_000_000_000));
+        assert_eq!(Resolution::Decimal(20).units_per_sec(), None);
+        assert_eq!(Resolution::Binary(64).units_per_sec(), None);
+        assert_eq!(Resolution::Binary(200).tsresol(), None);
+    }
+
+    #[test]
+    fn time_conversions() {
+        for &(secs, nanos) in &[(0, 0), (1_500_000_000, 123), (-1, 999_999_999), (-86_400, 0), (5_000_000_000, 1)] {
+            let ts = Timestamp::new(secs, nanos).unwrap();
+            assert_eq!(Timestamp::from_time(ts.to_time().unwrap()), Some(ts));
+            assert_eq!(Timestamp::try_from(SystemTime::try_from(ts).unwrap()), Ok(ts));
+            #[cfg(feature = "time03")]
+            assert_eq!(Timestamp::from(time03::OffsetDateTime::try_from(ts).unwrap()), ts);
+            #[cfg(feature = "chrono")]
+            assert_eq!(Timestamp::from(chrono::DateTime::<chrono::Utc>::try_from(ts).unwrap()), ts);
+        }
+
+        // beyond the year 1000000
+        let far = Timestamp::new(1 << 45, 0).unwrap();
+        #[cfg(feature = "time03")]
+        assert!(time03::OffsetDateTime::try_from(far).is_err());
+        #[cfg(feature = "chrono")]
+        assert!(chrono::DateTime::<chrono::Utc>::try_from(far).is_err());
+        let _ = far;
+    }
+}
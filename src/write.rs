@@ -1,13 +1,15 @@
-use std::convert::{TryInto,TryFrom};
+use std::convert::TryFrom;
 use std::io;
-#[cfg(not(feature = "time"))]
-use std::time::UNIX_EPOCH;
+use std::ops::Deref;
 
+use super::compress::Compress;
 use super::def;
 use super::PcapError;
 use super::CapturedPacket;
-use super::Time;
+use super::InterfaceOptions;
+use super::{Resolution, Rounding, Timestamp};
 
+use bytepack::Packed;
 use bytepack::Packer as NativePacker;
 
 #[cfg(target_endian = "big")]
@@ -22,19 +24,16 @@ pub use super::FileOptions as WriteOptions;
 pub struct PcapWriter<W: io::Write> {
     writer: W,
     opts: WriteOptions,
+    rounding: Rounding,
+    lossy_timestamps: u64,
 }
 
 impl<W: io::Write> PcapWriter<W> {
     /// Create a new `PcapWriter` that writes the packet capture data to the specified `Write`.
+    ///
+    /// To write compressed captures, wrap the writer in a `compress::Compress` first.
     pub fn new(mut writer: W, opts: WriteOptions) -> Result<Self, PcapError> {
-        let fh = def::PcapFileHeaderInFile::new(opts)
-            .ok_or(PcapError::InvalidFileHeader)?;
-
-        if opts.non_native_byte_order {
-            NonNativePacker::pack(&mut writer, fh)?
-        } else {
-            NativePacker::pack(&mut writer,fh)?;
-        }
+        writer.write_all(&encode_file_header(opts)?)?;
 
         PcapWriter::append_unchecked(writer, opts)
     }
@@ -46,61 +45,230 @@ impl<W: io::Write> PcapWriter<W> {
     /// created on other architectures or from another tool/library might use different
     /// timestamp formats or endianness, leading to data corruption.
     pub fn append_unchecked(writer: W, opts: WriteOptions) -> Result<Self, PcapError> {
-        Ok(PcapWriter { writer, opts, })
+        Ok(PcapWriter { writer, opts, rounding: Rounding::default(), lossy_timestamps: 0 })
     }
 
     /// Create a new `PcapWriter` that appends the packets to an existing stream, which must
     /// support `Read + Write + Seek` so that the correct format options for the file can be
-    /// determined.
+    /// determined. Compressed streams are rejected, use `append_compressed` for them.
     pub fn append(mut stream: W) -> Result<Self, PcapError>
         where W: io::Read + io::Seek
     {
         stream.seek(io::SeekFrom::Start(0))?;
         let (opts, reader) = super::read::PcapReader::new(stream)?;
+        if reader.codec().is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "use append_compressed to append to compressed captures").into());
+        }
 
         let mut writer = reader.take_reader();
         writer.seek(io::SeekFrom::End(0))?;
 
-        Ok(PcapWriter { writer, opts, })
+        PcapWriter::append_unchecked(writer, opts)
+    }
+
+    /// Sets how timestamps are rounded if the file can't represent them exactly. They are
+    /// rounded to the nearest unit by default.
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+    }
+
+    /// The number of packets written so far whose timestamp had to be rounded.
+    pub fn lossy_timestamps(&self) -> u64 {
+        self.lossy_timestamps
     }
 
     /// Write a package to the capture file.
     pub fn write(&mut self, packet: &CapturedPacket) -> Result<(), PcapError> {
-        let duration = secs_since_epoch(packet.time);
-        let (mut sec, nsec) = match duration {
-            Some((sec, nsec)) => (sec, nsec),
-            None => return Err(PcapError::InvalidDate),
-        };
+        let (record_header, len) = encode_record_header(&self.opts, self.rounding, packet)?;
+        self.lossy_timestamps += u64::from(record_header.lossy_timestamp);
+        self.writer.write_all(&record_header)?;
+        self.writer.write_all(&packet.data[..len]).map_err(PcapError::from)
+    }
 
-        let len = u32::try_from(packet.data.len()).or(Err(PcapError::InvalidPacketSize))?;
-        let len = u32::min(len, self.opts.snaplen as u32);
-        let orig_len = u32::try_from(packet.orig_len).or(Err(PcapError::InvalidPacketSize))?;
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
 
-        let subsec = if self.opts.high_res_timestamps {
-            nsec
-        } else {
-            let usec = div_rounded(nsec, 1000);
-            if usec == 1_000_000 {
-                sec = sec.saturating_add(1);
-                0
-            } else {
-                usec
-            }
+    /// Destroys this `PcapWriter` and returns access to the underlying `Write`.
+    pub fn take_writer(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying `Write`, then destroys this `PcapWriter` and returns it. See
+    /// `atomic::AtomicPcapWriter` for also syncing the file to disk.
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Gets a reference to the underlying `Write`.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// The options used by this `PcapWriter`.
+    pub fn get_options(&self) -> WriteOptions {
+        self.opts
+    }
+}
+
+impl<W: io::Read + io::Write + io::Seek> PcapWriter<Compress<W>> {
+    /// Create a new `PcapWriter` that appends the packets to an existing, possibly compressed
+    /// stream. Like `append`, this determines the correct format options from the file header.
+    ///
+    /// The packets are compressed with the same format as the existing data, using the given
+    /// compression `level`, and written as a new compressed stream after the existing ones.
+    /// Uncompressed files stay uncompressed. Call `Compress::finish` on the writer returned by
+    /// `take_writer` to complete the file.
+    pub fn append_compressed(mut stream: W, level: u32) -> Result<Self, PcapError> {
+        stream.seek(io::SeekFrom::Start(0))?;
+        let (opts, reader) = super::read::PcapReader::new(&mut stream)?;
+        let codec = reader.codec();
+        drop(reader);
+
+        stream.seek(io::SeekFrom::End(0))?;
+        let writer = match codec {
+            Some(codec) => Compress::new(stream, codec, level)?,
+            None => Compress::uncompressed(stream),
         };
 
-        let record_header = def::PcapRecordHeader {
-            ts_sec: sec,
-            ts_usec: subsec,
-            incl_len: len,
-            orig_len,
+        PcapWriter::append_unchecked(writer, opts)
+    }
+}
+
+/// The `PcapNgWriter` struct allows writing packets to a packet capture in the pcapng format.
+///
+/// In contrast to the traditional format written by `PcapWriter`, a pcapng file can contain
+/// packets from several interfaces, each with its own link type, snap length and timestamp
+/// resolution.
+pub struct PcapNgWriter<W: io::Write> {
+    writer: W,
+    non_native_byte_order: bool,
+    interfaces: Vec<InterfaceOptions>,
+    rounding: Rounding,
+    lossy_timestamps: u64,
+}
+
+impl<W: io::Write> PcapNgWriter<W> {
+    /// Create a new `PcapNgWriter` that writes the packet capture data to the specified `Write`.
+    ///
+    /// This writes a Section Header Block, interfaces have to be registered with
+    /// `add_interface` before packets can be written.
+    pub fn new(writer: W, non_native_byte_order: bool) -> Result<Self, PcapError> {
+        let mut pcapw = PcapNgWriter {
+            writer,
+            non_native_byte_order,
+            interfaces: Vec::new(),
+            rounding: Rounding::default(),
+            lossy_timestamps: 0,
         };
 
-        if self.opts.non_native_byte_order {
-            NonNativePacker::pack(&mut self.writer, record_header)?
-        } else {
-            NativePacker::pack(&mut self.writer, record_header)?;
+        let mut shb = pcapw.builder();
+        shb.u32(def::NG_BYTE_ORDER_MAGIC)
+            .u16(def::NG_VERSION_MAJOR)
+            .u16(def::NG_VERSION_MINOR)
+            // section length is not specified
+            .u64(u64::MAX);
+        pcapw.write_block(def::NG_BLOCK_SECTION_HEADER, &shb.data, &[])?;
+
+        Ok(pcapw)
+    }
+
+    /// Writes an Interface Description Block for a new interface. Returns the id of the
+    /// interface, which is needed to write packets captured on it.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the linktype, snaplen or timestamp resolution
+    /// can't be stored in the file.
+    pub fn add_interface(&mut self, opts: InterfaceOptions) -> Result<u32, PcapError> {
+        let id = u32::try_from(self.interfaces.len())
+            .or(Err(invalid_input("too many interfaces")))?;
+        let linktype = u16::try_from(u32::from(opts.linktype))
+            .or(Err(invalid_input("linktype doesn't fit into an interface description")))?;
+        let snaplen = u32::try_from(opts.snaplen).or(Err(invalid_input("snaplen is too large")))?;
+        opts.ts_resolution.units_per_sec().ok_or_else(|| invalid_input("unsupported timestamp resolution"))?;
+        let tsresol = opts.ts_resolution.tsresol().ok_or_else(|| invalid_input("unsupported timestamp resolution"))?;
+
+        let mut idb = self.builder();
+        idb.u16(linktype).u16(0).u32(snaplen);
+        if opts.ts_resolution != Resolution::default() {
+            idb.option(def::NG_OPT_IF_TSRESOL, &[tsresol]);
+        }
+        if opts.ts_offset != 0 {
+            let mut offset = self.builder();
+            offset.u64(opts.ts_offset as u64);
+            idb.option(def::NG_OPT_IF_TSOFFSET, &offset.data);
+        }
+        if idb.data.len() > 8 {
+            idb.end_options();
         }
-        self.writer.write_all(&packet.data[..len as usize]).map_err(PcapError::from)
+        self.write_block(def::NG_BLOCK_INTERFACE_DESCRIPTION, &idb.data, &[])?;
+
+        self.interfaces.push(opts);
+        Ok(id)
+    }
+
+    /// Sets how timestamps are rounded if the resolution of an interface can't represent them
+    /// exactly. They are rounded to the nearest unit by default.
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+    }
+
+    /// The number of packets written so far whose timestamp had to be rounded.
+    pub fn lossy_timestamps(&self) -> u64 {
+        self.lossy_timestamps
+    }
+
+    /// Write a packet captured on the interface `interface_id` to the capture file, using an
+    /// Enhanced Packet Block.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if no interface with that id was added.
+    pub fn write(&mut self, interface_id: u32, packet: &CapturedPacket) -> Result<(), PcapError> {
+        let iface = usize::try_from(interface_id).ok()
+            .and_then(|id| self.interfaces.get(id))
+            .ok_or_else(|| invalid_input("unknown interface id"))?;
+
+        let timestamp = Timestamp::from_time(packet.time).ok_or(PcapError::InvalidDate)?;
+        let (timestamp, lossy) = def::ng_make_timestamp(timestamp, iface, self.rounding)
+            .ok_or(PcapError::TimestampOutOfRange)?;
+
+        let mut len = packet.data.len();
+        if iface.snaplen != 0 {
+            len = usize::min(len, iface.snaplen);
+        }
+        let incl_len = u32::try_from(len).or(Err(PcapError::InvalidPacketSize))?;
+        let orig_len = u32::try_from(packet.orig_len).or(Err(PcapError::InvalidPacketSize))?;
+
+        let mut epb = self.builder();
+        epb.u32(interface_id)
+            .timestamp(timestamp)
+            .u32(incl_len)
+            .u32(orig_len);
+        self.write_block(def::NG_BLOCK_ENHANCED_PACKET, &epb.data, &packet.data[..len])?;
+        self.lossy_timestamps += u64::from(lossy);
+        Ok(())
+    }
+
+    fn builder(&self) -> def::NgBlockBuilder {
+        def::NgBlockBuilder::new(self.non_native_byte_order)
+    }
+
+    /// Writes a block consisting of a fixed part in `head` and a variable-length `data` part.
+    fn write_block(&mut self, block_type: u32, head: &[u8], data: &[u8]) -> Result<(), PcapError> {
+        let padded_len = def::ng_padded(data.len());
+        let total_len = def::NG_BLOCK_OVERHEAD + head.len() + padded_len;
+        let total_len = u32::try_from(total_len).or(Err(PcapError::InvalidPacketSize))?;
+
+        let mut start = self.builder();
+        start.u32(block_type).u32(total_len);
+        self.writer.write_all(&start.data)?;
+        self.writer.write_all(head)?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&[0; 3][..padded_len - data.len()])?;
+
+        let mut end = self.builder();
+        end.u32(total_len);
+        self.writer.write_all(&end.data).map_err(PcapError::from)
     }
 
     /// Flushes the underlying writer.
@@ -108,30 +276,86 @@ impl<W: io::Write> PcapWriter<W> {
         self.writer.flush()
     }
 
-    /// Destroys this `PcapWriter` and returns access to the underlying `Write`.
+    /// Destroys this `PcapNgWriter` and returns access to the underlying `Write`.
     pub fn take_writer(self) -> W {
         self.writer
     }
 
-    /// The options used by this `PcapWriter`.
-    pub fn get_options(&self) -> WriteOptions {
-        self.opts
+    /// The interfaces that were added to this `PcapNgWriter`, in the order of their ids.
+    pub fn interfaces(&self) -> &[InterfaceOptions] {
+        &self.interfaces
     }
 }
-fn div_rounded(val: u32, divisor: u32) -> u32 {
-    (val + (divisor / 2)) / divisor
+
+/// An `io::ErrorKind::InvalidInput` error for invalid arguments of the writer.
+fn invalid_input(msg: &'static str) -> PcapError {
+    io::Error::new(io::ErrorKind::InvalidInput, msg).into()
 }
 
-#[cfg(not(feature = "time"))]
-fn secs_since_epoch(time: Time) -> Option<(u32, u32)> {
-    let duration = time.duration_since(UNIX_EPOCH).ok()?;
-    let sec = duration.as_secs().try_into().ok()?;
-    let nsec = duration.subsec_nanos();
-    Some((sec, nsec))
+/// Serializes `val` in the byte order of the file. `N` has to be the size of `T`.
+fn pack<T: Packed, const N: usize>(val: T, non_native_byte_order: bool) -> [u8; N] {
+    let mut buf = io::Cursor::new([0; N]);
+    if non_native_byte_order {
+        NonNativePacker::pack(&mut buf, val)
+    } else {
+        NativePacker::pack(&mut buf, val)
+    }.expect("buffer has the size of the value");
+    buf.into_inner()
 }
-#[cfg(feature = "time")]
-fn secs_since_epoch(time: Time) -> Option<(u32, u32)> {
-    let sec = time.sec.try_into().ok()?;
-    let nsec = time.nsec.try_into().ok()?;
-    Some((sec, nsec))
+
+/// Serializes the file header for a capture with the options `opts`.
+pub(crate) fn encode_file_header(opts: WriteOptions) -> Result<[u8; 24], PcapError> {
+    let fh = def::PcapFileHeaderInFile::new(opts)
+        .ok_or(PcapError::InvalidFileHeader)?;
+    Ok(pack(fh, opts.non_native_byte_order))
 }
+
+/// A serialized record header, including the additional fields of extended records.
+pub(crate) struct RecordHeader {
+    buf: [u8; 24],
+    len: usize,
+    /// `true` if the timestamp of the packet had to be rounded.
+    pub(crate) lossy_timestamp: bool,
+}
+impl Deref for RecordHeader {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Serializes the record header for `packet` in a capture with the options `opts`. Returns the
+/// header, and how many bytes of the packet data have to be written after it.
+pub(crate) fn encode_record_header(opts: &WriteOptions, rounding: Rounding, packet: &CapturedPacket) -> Result<(RecordHeader, usize), PcapError> {
+    let resolution = opts.resolution();
+    let units_per_sec = i128::from(resolution.units_per_sec().expect("microseconds and nanoseconds fit"));
+    let converted = Timestamp::from_time(packet.time).ok_or(PcapError::InvalidDate)?
+        .checked_sub_secs(i64::from(opts.utc_offset))
+        .and_then(|ts| ts.to_units(resolution, rounding))
+        .ok_or(PcapError::TimestampOutOfRange)?;
+    let sec = u32::try_from(converted.units.div_euclid(units_per_sec))
+        .or(Err(PcapError::TimestampOutOfRange))?;
+    let subsec = converted.units.rem_euclid(units_per_sec) as u32;
+
+    let len = u32::try_from(packet.data.len()).or(Err(PcapError::InvalidPacketSize))?;
+    let len = u32::min(len, opts.snaplen as u32);
+    let orig_len = u32::try_from(packet.orig_len).or(Err(PcapError::InvalidPacketSize))?;
+
+    let record_header = def::PcapRecordHeader {
+        ts_sec: sec,
+        ts_usec: subsec,
+        incl_len: len,
+        orig_len,
+    };
+    let mut header = RecordHeader { buf: [0; 24], len: 0, lossy_timestamp: converted.lossy };
+    header.buf[..16].copy_from_slice(&pack::<_, 16>(record_header, opts.non_native_byte_order));
+    if opts.variant.extended_records() {
+        let extended = def::PcapExtendedRecordHeader::from(packet.extended.unwrap_or_default());
+        header.buf[16..].copy_from_slice(&pack::<_, 8>(extended, opts.non_native_byte_order));
+    }
+    // the additional bytes of other variants are left as zeroes
+    header.len = opts.variant.record_header_len();
+    Ok((header, len as usize))
+}
+